@@ -0,0 +1,74 @@
+use disrecord::recorder::recorder::Recorder;
+use serenity::all::{CommandInteraction, CommandOptionType, Context, CreateAttachment, CreateCommandOption, EditInteractionResponse, InteractionContext};
+use serenity::builder::{CreateCommand, CreateInteractionResponse, CreateInteractionResponseMessage};
+
+pub const NAME: &str = "clip";
+
+pub async fn run(ctx: &Context, cmd: &CommandInteraction) {
+    let guild_id = cmd.guild_id.unwrap();
+    let minutes = cmd.data.options.first().and_then(|x| x.value.as_i64()).unwrap_or(1).max(1) as u64;
+
+    cmd.create_response(ctx, CreateInteractionResponse::Defer(CreateInteractionResponseMessage::new())).await.unwrap_or_else(|e| {
+        error!("Error responding to the interaction: {e:?}");
+    });
+
+    let rec_man = Recorder::get(ctx).await.expect("RecordManager doesn't exist!");
+
+    let zip_path = match rec_man.clip(guild_id, minutes).await {
+        Ok(x) => x,
+        Err(e) => {
+            let resp = EditInteractionResponse::new().content(format!("Failed to clip recording: {e}"));
+            if let Err(e) = cmd.edit_response(ctx, resp).await {
+                error!("Error editing response to the interaction: {e:?}");
+            }
+            return;
+        }
+    };
+
+    let posted = match CreateAttachment::path(&zip_path).await {
+        Ok(attachment) => {
+            let resp = EditInteractionResponse::new()
+                .content(format!("Here are the last {minutes} minute(s)!"))
+                .new_attachment(attachment);
+
+            match cmd.edit_response(ctx, resp).await {
+                Ok(_) => true,
+                Err(e) => {
+                    error!("Error editing response to the interaction: {e:?}");
+                    false
+                }
+            }
+        }
+        Err(e) => {
+            error!("Failed to create attachment: {e:?}");
+            false
+        }
+    };
+
+    // A posted clip isn't needed locally; one that couldn't be posted is kept, encrypted for guilds with keys.
+    let kept = rec_man.settle_clip(guild_id, &zip_path, posted).await;
+
+    if !posted {
+        let content = match kept {
+            Some(path) => format!("Failed to send clip (file too large?), it was saved to `{}` instead.", path.display()),
+            None => "Failed to send clip (file too large?).".to_string(),
+        };
+
+        if let Err(e) = cmd.edit_response(ctx, EditInteractionResponse::new().content(content)).await {
+            error!("Error editing response to explain why the clip wasn't sent: {e:?}");
+        }
+    }
+}
+
+/// `max_minutes` is how much history the recorder keeps, so Discord rejects longer clips before they reach us.
+pub fn register(max_minutes: u64) -> CreateCommand {
+    CreateCommand::new(NAME)
+        .description("Save the last few minutes of the current recording")
+        .add_context(InteractionContext::Guild)
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Integer, "minutes", "How many minutes to clip")
+                .required(true)
+                .min_int_value(1)
+                .max_int_value(max_minutes)
+        )
+}
\ No newline at end of file
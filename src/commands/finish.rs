@@ -1,9 +1,25 @@
-use crate::recorder::recorder::Recorder;
+use crate::commands::reset_presence;
+use disrecord::recorder::recorder::Recorder;
+use disrecord::recorder::storage::StorageLocation;
+use disrecord::recorder::ZipProgress;
+use std::time::Duration;
 use serenity::all::{CommandInteraction, Context, CreateAttachment, CreateEmbed, CreateEmbedFooter, CreateInteractionResponseFollowup, CreateInteractionResponseMessage, EditInteractionResponse, InteractionContext};
 use serenity::builder::{CreateCommand, CreateInteractionResponse};
 
 pub const NAME: &str = "finish";
 
+/// How often the packaging message is edited while the zip is being written.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+fn format_size(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+fn format_progress(progress: &ZipProgress) -> String {
+    let percent = (progress.bytes_done * 100).checked_div(progress.bytes_total).unwrap_or(100);
+    format!("{}/{} files, {percent}% ({} of {})", progress.files_done, progress.files_total, format_size(progress.bytes_done), format_size(progress.bytes_total))
+}
+
 pub async fn run(ctx: &Context, cmd: &CommandInteraction) {
     let guild_id = cmd.guild_id.unwrap();
 
@@ -13,7 +29,10 @@ pub async fn run(ctx: &Context, cmd: &CommandInteraction) {
 
     let rec_man = Recorder::get(ctx).await.expect("RecordManager doesn't exist!");
 
-    match rec_man.finish(ctx, guild_id).await {
+    let res = rec_man.finish(ctx, guild_id).await;
+    reset_presence(ctx, guild_id).await;
+
+    match res {
         Ok(metadata) => {
             let duration = metadata.ended.signed_duration_since(metadata.started);
 
@@ -27,27 +46,89 @@ pub async fn run(ctx: &Context, cmd: &CommandInteraction) {
             }
             user_string.pop();
 
+            let mut silent_string = String::new();
+            for silent_user in metadata.silent_users.iter() {
+                silent_string += format!("<@{}> ", silent_user.get()).as_str()
+            }
+            silent_string.pop();
+
+            let write_warning = metadata.first_error.as_ref().map(|e| {
+                format!("{} error(s) while writing, some audio may be missing.\nFirst: {e}", metadata.write_errors)
+            });
+
             let hours = duration.num_hours();
             let minutes = duration.num_minutes() - (duration.num_hours() * 60);
             let seconds  = duration.num_seconds() - (duration.num_minutes() * 60);
 
-            let resp = EditInteractionResponse::new()
-                .embed(CreateEmbed::new()
-                    .title("Recording finished!")
-                    .field("Duration", format!("{hours}h {minutes:02}m {seconds:02}s"), false)
-                    .field("Users Recorded", user_string, false)
-                    .footer(CreateEmbedFooter::new("For recording started"))
-                    .timestamp(metadata.started)
-                );
+            let embed = CreateEmbed::new()
+                .title("Recording finished!")
+                .field("Duration", format!("{hours}h {minutes:02}m {seconds:02}s"), false)
+                .field("Users Recorded", user_string, false)
+                .fields((!silent_string.is_empty()).then_some(("Present but Silent", silent_string, false)))
+                .fields(write_warning.map(|x| ("Warning", x, false)))
+                .field("Recording ID", format!("`{}`", metadata.id), false)
+                .footer(CreateEmbedFooter::new("For recording started"))
+                .timestamp(metadata.started);
 
-            if let Err(e) = cmd.edit_response(ctx, resp).await {
+            if let Err(e) = cmd.edit_response(ctx, EditInteractionResponse::new().embed(embed.clone())).await {
                 error!("Error editing response to the interaction: {e:?}");
             }
 
-            match metadata.zip_rx.await {
+            let progress_msg = match cmd.create_followup(ctx, CreateInteractionResponseFollowup::new().content("Packaging recording...")).await {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    error!("Error sending followup to the interaction: {e:?}");
+                    None
+                }
+            };
+
+            let mut zip_rx = metadata.zip_rx;
+            let mut zip_progress = metadata.zip_progress;
+            let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+
+            let zip_res = loop {
+                tokio::select! {
+                    res = &mut zip_rx => break res,
+                    _ = interval.tick() => {
+                        if !zip_progress.has_changed().unwrap_or(false) {
+                            continue;
+                        }
+
+                        let progress = *zip_progress.borrow_and_update();
+                        if let Some(msg) = &progress_msg {
+                            let edit = CreateInteractionResponseFollowup::new().content(format!("Packaging recording... {}", format_progress(&progress)));
+                            if let Err(e) = cmd.edit_followup(ctx, msg.id, edit).await {
+                                warn!("Error updating zip progress: {e:?}");
+                            }
+                        }
+                    }
+                }
+            };
+
+            if let Some(msg) = &progress_msg {
+                let progress = *zip_progress.borrow();
+                let content = match &zip_res {
+                    Ok(Ok(_)) => format!("Packaged {} file(s), {}", progress.files_total, format_size(progress.bytes_total)),
+                    Ok(Err(e)) => format!("Failed to package recording: {e}"),
+                    Err(_) => "Failed to package recording!".to_string(),
+                };
+
+                if let Err(e) = cmd.edit_followup(ctx, msg.id, CreateInteractionResponseFollowup::new().content(content)).await {
+                    warn!("Error updating zip progress: {e:?}");
+                }
+            }
+
+            if let Ok(Ok(stored)) = &zip_res {
+                let embed = embed.field("Zip SHA-256", format!("`{}`", stored.sha256), false);
+                if let Err(e) = cmd.edit_response(ctx, EditInteractionResponse::new().embed(embed)).await {
+                    error!("Error editing response to the interaction: {e:?}");
+                }
+            }
+
+            match zip_res {
                 Ok(x) => {
-                    match x {
-                        Ok(zip_path) => {
+                    match x.map(|x| x.location) {
+                        Ok(StorageLocation::Local(zip_path)) => {
                             let fup_attachment = match CreateAttachment::path(zip_path).await {
                                 Ok(x) => x,
                                 Err(e) => {
@@ -66,6 +147,12 @@ pub async fn run(ctx: &Context, cmd: &CommandInteraction) {
                                 }
                             }
                         }
+                        Ok(location) => {
+                            let followup = CreateInteractionResponseFollowup::new().content(format!("Recording saved to `{location}`"));
+                            if let Err(e) = cmd.create_followup(ctx, followup).await {
+                                error!("Error sending followup to the interaction: {e:?}");
+                            }
+                        }
                         Err(e) => {
                             error!("Failed to zip recordings: {e:?}");
                         }
@@ -1,28 +1,20 @@
-use serenity::all::{ActivityData, ChannelId, ChannelType, CommandInteraction, Context, GuildId, OnlineStatus, UserId};
+use crate::presence::PresenceManager;
+use serenity::all::{ChannelId, ChannelType, CommandInteraction, Context, GuildId, UserId};
 
 pub mod start;
 pub mod finish;
 pub mod rejoin;
+pub mod recordings;
+pub mod clip;
 
 pub async fn set_presence(ctx: &Context, guild_id: GuildId) {
-    let activity = ActivityData::custom("Recording...");
-    let status = OnlineStatus::DoNotDisturb;
-    ctx.set_presence(Some(activity), status);
-
-    let bot_name = ctx.cache.current_user().display_name().to_string();
-
-    guild_id.edit_nickname(ctx, Some(format!("🔴 {bot_name}").as_str())).await.unwrap_or_else(|e| {
-        warn!("Failed to set nickname: {e:?}");
-    });
+    let presence = PresenceManager::get(ctx).await.expect("PresenceManager doesn't exist!");
+    presence.start(ctx, guild_id).await;
 }
 
 pub async fn reset_presence(ctx: &Context, guild_id: GuildId) {
-    let status = OnlineStatus::Online;
-    ctx.set_presence(None, status);
-
-    guild_id.edit_nickname(ctx, None).await.unwrap_or_else(|e| {
-        warn!("Failed to set nickname: {e}");
-    });
+    let presence = PresenceManager::get(ctx).await.expect("PresenceManager doesn't exist!");
+    presence.stop(ctx, guild_id).await;
 }
 
 pub async fn get_channel_or_default_current(ctx: &Context, cmd: &CommandInteraction) -> Option<ChannelId> {
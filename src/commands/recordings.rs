@@ -0,0 +1,99 @@
+use disrecord::recorder::checksums::ChecksumStatus;
+use disrecord::recorder::recorder::Recorder;
+use serenity::all::{CommandInteraction, CommandOptionType, Context, CreateCommandOption, CreateEmbed, EditInteractionResponse, InteractionContext, ResolvedOption, ResolvedValue};
+use serenity::builder::{CreateCommand, CreateInteractionResponse, CreateInteractionResponseMessage};
+
+pub const NAME: &str = "recordings";
+
+/// Most problem files listed in a single reply, to stay inside Discord's embed limits.
+const MAX_LISTED: usize = 20;
+
+pub async fn run(ctx: &Context, cmd: &CommandInteraction) {
+    let options = cmd.data.options();
+    match options.first() {
+        Some(ResolvedOption { name: "verify", value: ResolvedValue::SubCommand(sub_options), .. }) => {
+            let id = sub_options.iter().find_map(|x| match x {
+                ResolvedOption { name: "id", value: ResolvedValue::String(id), .. } => Some(*id),
+                _ => None,
+            });
+
+            match id {
+                Some(id) => verify(ctx, cmd, id).await,
+                None => error!("/{NAME} verify was called without an ID"),
+            }
+        }
+        _ => {
+            error!("Unknown /{NAME} subcommand: {:?}", cmd.data.options);
+        }
+    }
+}
+
+async fn verify(ctx: &Context, cmd: &CommandInteraction, id: &str) {
+    let guild_id = cmd.guild_id.unwrap();
+
+    cmd.create_response(ctx, CreateInteractionResponse::Defer(CreateInteractionResponseMessage::new().ephemeral(true))).await.unwrap_or_else(|e| {
+        error!("Error responding to the interaction: {e:?}");
+    });
+
+    let rec_man = Recorder::get(ctx).await.expect("RecordManager doesn't exist!");
+
+    let resp = match rec_man.verify(guild_id, id).await {
+        Ok(results) => {
+            let deleted = results.iter().filter(|x| x.status == ChecksumStatus::DeletedAfterEncryption).count();
+            let problems = results.iter().filter(|x| !matches!(x.status, ChecksumStatus::Ok | ChecksumStatus::DeletedAfterEncryption)).collect::<Vec<_>>();
+            let deleted_note = match deleted {
+                0 => String::new(),
+                n => format!("\n{n} plaintext file(s) were deleted after encryption."),
+            };
+
+            if results.is_empty() {
+                EditInteractionResponse::new().content(format!("Recording `{id}` has no checksums to verify."))
+            } else if problems.is_empty() {
+                EditInteractionResponse::new().embed(CreateEmbed::new()
+                    .title("Recording verified")
+                    .description(format!("All {} remaining file(s) in `{id}` match their checksums.{deleted_note}", results.len() - deleted))
+                )
+            } else {
+                let mut problem_string = String::new();
+                for problem in problems.iter().take(MAX_LISTED) {
+                    let status = match problem.status {
+                        ChecksumStatus::Mismatch => "modified",
+                        ChecksumStatus::Missing => "missing",
+                        ChecksumStatus::DeletedAfterEncryption => "deleted after encryption",
+                        ChecksumStatus::Ok => "ok",
+                    };
+                    problem_string += format!("`{}`: {status}\n", problem.file_name).as_str();
+                }
+                if problems.len() > MAX_LISTED {
+                    problem_string += format!("...and {} more", problems.len() - MAX_LISTED).as_str();
+                }
+
+                EditInteractionResponse::new().embed(CreateEmbed::new()
+                    .title("Recording failed verification!")
+                    .description(format!("{} of {} file(s) in `{id}` do not match their checksums.{deleted_note}", problems.len(), results.len() - deleted))
+                    .field("Files", problem_string, false)
+                )
+            }
+        }
+        Err(e) => {
+            EditInteractionResponse::new().content(format!("Failed to verify recording: {e}"))
+        }
+    };
+
+    if let Err(e) = cmd.edit_response(ctx, resp).await {
+        error!("Error editing response to the interaction: {e:?}");
+    }
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new(NAME)
+        .description("Manage finished recordings")
+        .add_context(InteractionContext::Guild)
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::SubCommand, "verify", "Check a recording's files against its checksums")
+                .add_sub_option(
+                    CreateCommandOption::new(CommandOptionType::String, "id", "Recording ID, as shown when it was finished")
+                        .required(true)
+                )
+        )
+}
\ No newline at end of file
@@ -1,5 +1,5 @@
 use crate::commands::get_channel_or_default_current;
-use crate::recorder::recorder::Recorder;
+use disrecord::recorder::recorder::Recorder;
 use serenity::all::{ChannelType, CommandInteraction, CommandOptionType, Context, CreateCommandOption, InteractionContext};
 use serenity::builder::{CreateCommand, CreateInteractionResponse, CreateInteractionResponseMessage};
 
@@ -1,5 +1,5 @@
 use crate::commands::{get_channel_or_default_current, reset_presence, set_presence};
-use crate::recorder::recorder::Recorder;
+use disrecord::recorder::recorder::Recorder;
 use serenity::all::{ChannelId, ChannelType, CommandInteraction, CommandOptionType, Context, CreateCommandOption, CreateInteractionResponseMessage, GuildId, InteractionContext};
 use serenity::builder::{CreateCommand, CreateInteractionResponse};
 
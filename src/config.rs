@@ -0,0 +1,708 @@
+use chrono::format::{Item, StrftimeItems};
+use disrecord::recorder::durability::SyncPolicy;
+use disrecord::recorder::encryption::EncryptionConfig;
+use disrecord::recorder::storage::local::LocalStorage;
+use disrecord::recorder::storage::s3::{S3Config, S3Storage};
+use disrecord::recorder::storage::sftp::{SftpConfig, SftpStorage};
+use disrecord::recorder::storage::StorageBackend;
+use disrecord::recorder::track_name::{TrackNameTemplate, DEFAULT_TRACK_NAME};
+use disrecord::recorder::{RecorderConfig, SegmentPolicy};
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+use songbird::driver::DecodeMode;
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use toml::{Table, Value};
+
+/// Config file read when `DISRECORD_CONFIG` isn't set. Running without one is fine; everything has a default.
+pub const DEFAULT_CONFIG_FILE: &str = "disrecord.toml";
+
+/// Prefix of the environment variables that override settings, e.g. `DISRECORD_RECORDING_BASE_DIR`.
+const ENV_PREFIX: &str = "DISRECORD_";
+
+/// Longest a segment or the clip history may be, so that converting it to seconds can't overflow.
+const MAX_MINUTES: u64 = 7 * 24 * 60;
+
+/// Largest a segment may be, so that converting it to bytes can't overflow.
+const MAX_SEGMENT_MB: u64 = 1024 * 1024;
+
+/// Older environment variables, still honoured so existing `.env` files keep working.
+const LEGACY_VARS: &[(&str, &str)] = &[
+    ("BOT_TOKEN", "discord.bot_token"),
+    ("APP_ID", "discord.app_id"),
+    ("LOG_LEVEL", "logging.level"),
+    ("LOG_LEVEL_ALL", "logging.level_all"),
+    ("SYNC_POLICY", "recording.sync_policy"),
+    ("SEGMENT_MINUTES", "recording.segment_minutes"),
+    ("SEGMENT_MB", "recording.segment_mb"),
+    ("CLIP_HISTORY_MINUTES", "recording.clip_history_minutes"),
+    ("CAPTURE_PACKETS", "recording.capture_packets"),
+    ("SHUTDOWN_TIMEOUT_SECONDS", "discord.shutdown_timeout_seconds"),
+    ("STORAGE_BACKEND", "storage.backend"),
+    ("STORAGE_LOCAL_DIR", "storage.local_dir"),
+    ("S3_ENDPOINT", "storage.s3.endpoint"),
+    ("S3_REGION", "storage.s3.region"),
+    ("S3_BUCKET", "storage.s3.bucket"),
+    ("S3_ACCESS_KEY_ID", "storage.s3.access_key_id"),
+    ("S3_SECRET_ACCESS_KEY", "storage.s3.secret_access_key"),
+    ("S3_PREFIX", "storage.s3.prefix"),
+    ("S3_ALLOW_HTTP", "storage.s3.allow_http"),
+    ("SFTP_HOST", "storage.sftp.host"),
+    ("SFTP_PORT", "storage.sftp.port"),
+    ("SFTP_USERNAME", "storage.sftp.username"),
+    ("SFTP_PRIVATE_KEY", "storage.sftp.private_key"),
+    ("SFTP_PASSWORD", "storage.sftp.password"),
+    ("SFTP_KNOWN_HOSTS", "storage.sftp.known_hosts"),
+    ("SFTP_REMOTE_DIR", "storage.sftp.remote_dir"),
+    ("ENCRYPTION_KEYS", "encryption.keys_file"),
+    ("ENCRYPTION_DELETE_PLAINTEXT", "encryption.delete_plaintext"),
+];
+
+/// Every setting of the bot. Optional paths and secrets are left empty to mean "not set".
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub discord: DiscordConfig,
+    pub logging: LoggingConfig,
+    pub recording: RecordingConfig,
+    pub output: OutputConfig,
+    pub storage: StorageConfig,
+    pub encryption: EncryptionSection,
+    pub http: HttpConfig,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub bot_token: String,
+    pub app_id: u64,
+    /// `decrypt` or `decode`. Tracks are muxed from the Opus packets, so decoding them only costs CPU.
+    pub decode_mode: String,
+    /// How long to wait for recordings to be finished and packaged on shutdown.
+    pub shutdown_timeout_seconds: u64,
+}
+
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        Self {
+            bot_token: String::new(),
+            app_id: 0,
+            decode_mode: "decrypt".to_string(),
+            shutdown_timeout_seconds: 120,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Level for disrecord's own messages.
+    pub level: String,
+    /// Level for everything else, e.g. serenity and songbird.
+    pub level_all: String,
+    /// Colour the console output.
+    pub color: bool,
+    /// Log file; empty to only log to the console.
+    pub file: String,
+    /// Size in MiB at which the log file is rotated; 0 to let it grow forever.
+    pub max_size_mb: u64,
+    /// Number of rotated log files to keep.
+    pub keep_files: u32,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "trace".to_string(),
+            level_all: "warn".to_string(),
+            color: true,
+            file: "disrecord.log".to_string(),
+            max_size_mb: 0,
+            keep_files: 5,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    pub base_dir: PathBuf,
+    pub jitter_buffer_depth: usize,
+    /// Voice ticks waiting for a call's writer before new ones are dropped.
+    pub queue_depth: usize,
+    pub repair_lost_packets: bool,
+    /// `never`, `page`, `pages:<n>` or `seconds:<n>`.
+    pub sync_policy: String,
+    /// Split tracks into a new file after this many minutes; 0 to never split by length.
+    pub segment_minutes: u64,
+    /// Split tracks into a new file after this many MiB; 0 to never split by size.
+    pub segment_mb: u64,
+    pub clip_history_minutes: u64,
+    pub capture_packets: bool,
+    /// Log the voice channel's text chat. Needs the privileged message content intent.
+    pub capture_chat: bool,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            base_dir: PathBuf::from("recordings"),
+            jitter_buffer_depth: 3,
+            queue_depth: 1024,
+            repair_lost_packets: false,
+            sync_policy: "seconds:10".to_string(),
+            segment_minutes: 0,
+            segment_mb: 0,
+            clip_history_minutes: 10,
+            capture_packets: false,
+            capture_chat: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// `strftime` format of each recording's directory name.
+    pub subdir_format: String,
+    /// Template for track file names; see `TrackNameTemplate`.
+    pub track_name: String,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            subdir_format: "%Y_%m_%d_%H_%M_%S".to_string(),
+            track_name: DEFAULT_TRACK_NAME.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// `local`, `s3` or `sftp`.
+    pub backend: String,
+    /// Where local recordings are kept; defaults to `recording.base_dir`.
+    pub local_dir: String,
+    pub s3: S3Section,
+    pub sftp: SftpSection,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: "local".to_string(),
+            local_dir: String::new(),
+            s3: S3Section::default(),
+            sftp: SftpSection::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Section {
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub prefix: String,
+    pub allow_http: bool,
+}
+
+impl Default for S3Section {
+    fn default() -> Self {
+        Self {
+            endpoint: String::new(),
+            region: "us-east-1".to_string(),
+            bucket: String::new(),
+            access_key_id: String::new(),
+            secret_access_key: String::new(),
+            prefix: String::new(),
+            allow_http: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SftpSection {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub private_key: String,
+    pub password: String,
+    pub known_hosts: String,
+    pub remote_dir: String,
+}
+
+impl Default for SftpSection {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            port: 22,
+            username: String::new(),
+            private_key: String::new(),
+            password: String::new(),
+            known_hosts: String::new(),
+            remote_dir: ".".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncryptionSection {
+    /// File of `<guild_id> <age public key>` lines; empty to leave recordings unencrypted.
+    pub keys_file: String,
+    pub delete_plaintext: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Address to serve `/metrics`, `/healthz` and `/readyz` on; empty to turn them off.
+    pub listen: String,
+    /// How long a call's writer can spend on one update before `/readyz` reports it as stalled.
+    pub writer_stall_seconds: u64,
+    /// Also fail `/healthz` on a stalled writer, so that the bot is restarted. Off by default, as a slow disk stalls
+    /// writers too and restarting doesn't help with that.
+    pub restart_on_stall: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:9187".to_string(),
+            writer_stall_seconds: 30,
+            restart_on_stall: false,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file, applies any environment overrides and checks the result, building the recorder's
+    /// configuration from it so that keys files and storage backends are validated on startup too.
+    pub fn load() -> Result<(Self, RecorderConfig), String> {
+        let path = env::var(format!("{ENV_PREFIX}CONFIG")).map(PathBuf::from).ok();
+
+        let mut value = Value::try_from(Config::default()).map_err(|e| format!("Failed to build default config: {e}"))?;
+
+        let file = path.clone().unwrap_or(PathBuf::from(DEFAULT_CONFIG_FILE));
+        match std::fs::read_to_string(&file) {
+            Ok(contents) => {
+                let table = contents.parse::<Table>().map_err(|e| format!("{}: {e}", file.display()))?;
+                merge(&mut value, Value::Table(table));
+            }
+            // Only a missing default file is fine; one that was asked for has to exist.
+            Err(e) if path.is_none() && e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to read {}: {e}", file.display())),
+        }
+
+        apply_env(&mut value, &env::vars().collect())?;
+
+        let config: Config = value.try_into().map_err(|e| format!("{}: {e}", file.display()))?;
+        config.validated()
+    }
+
+    /// Checks the settings, then builds the recorder's configuration from them.
+    fn validated(self) -> Result<(Self, RecorderConfig), String> {
+        let problems = self.problems();
+        if !problems.is_empty() {
+            return Err(problems.join("\n"));
+        }
+
+        let recorder = self.recorder_config()?;
+        Ok((self, recorder))
+    }
+
+    /// Everything wrong with the settings, so they can all be fixed in one go.
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.discord.bot_token.is_empty() {
+            problems.push("discord.bot_token must be set".to_string());
+        }
+        if self.discord.app_id == 0 {
+            problems.push("discord.app_id must be set".to_string());
+        }
+        if let Err(e) = self.decode_mode() {
+            problems.push(e);
+        }
+
+        for (name, level) in [("logging.level", &self.logging.level), ("logging.level_all", &self.logging.level_all)] {
+            if LevelFilter::from_str(level).is_err() {
+                problems.push(format!("{name}: unknown log level `{level}`"));
+            }
+        }
+        if self.logging.max_size_mb > 0 && self.logging.file.is_empty() {
+            problems.push("logging.max_size_mb is set but logging.file is empty".to_string());
+        }
+
+        if self.recording.jitter_buffer_depth == 0 {
+            problems.push("recording.jitter_buffer_depth must be at least 1".to_string());
+        }
+        if self.recording.queue_depth == 0 {
+            problems.push("recording.queue_depth must be at least 1".to_string());
+        }
+        if let Err(e) = self.recording.sync_policy.parse::<SyncPolicy>() {
+            problems.push(format!("recording.sync_policy: {e}"));
+        }
+        for (name, minutes) in [("recording.segment_minutes", self.recording.segment_minutes), ("recording.clip_history_minutes", self.recording.clip_history_minutes)] {
+            if minutes > MAX_MINUTES {
+                problems.push(format!("{name} must be at most {MAX_MINUTES} (a week)"));
+            }
+        }
+        if self.recording.segment_mb > MAX_SEGMENT_MB {
+            problems.push(format!("recording.segment_mb must be at most {MAX_SEGMENT_MB} (1 TiB)"));
+        }
+
+        let subdir_format = &self.output.subdir_format;
+        if subdir_format.is_empty() || subdir_format.contains(['/', '\\']) || StrftimeItems::new(subdir_format).any(|x| x == Item::Error) {
+            problems.push(format!("output.subdir_format: `{subdir_format}` is not a valid directory name format"));
+        }
+        if let Err(e) = TrackNameTemplate::parse(&self.output.track_name) {
+            problems.push(format!("output.track_name: {e}"));
+        }
+
+        let storage = &self.storage;
+        let required = match storage.backend.as_str() {
+            "local" => vec![],
+            "s3" => vec![
+                ("storage.s3.bucket", &storage.s3.bucket),
+                ("storage.s3.access_key_id", &storage.s3.access_key_id),
+                ("storage.s3.secret_access_key", &storage.s3.secret_access_key),
+            ],
+            "sftp" => vec![
+                ("storage.sftp.host", &storage.sftp.host),
+                ("storage.sftp.username", &storage.sftp.username),
+            ],
+            x => {
+                problems.push(format!("storage.backend: unknown backend `{x}` (expected local, s3 or sftp)"));
+                vec![]
+            }
+        };
+        for (name, value) in required {
+            if value.is_empty() {
+                problems.push(format!("{name} must be set for the {} backend", storage.backend));
+            }
+        }
+        if storage.backend == "sftp" && storage.sftp.private_key.is_empty() && storage.sftp.password.is_empty() {
+            problems.push("storage.sftp.private_key or storage.sftp.password must be set".to_string());
+        }
+
+        if !self.encryption.keys_file.is_empty() && !Path::new(&self.encryption.keys_file).is_file() {
+            problems.push(format!("encryption.keys_file: {} does not exist", self.encryption.keys_file));
+        }
+        if self.encryption.delete_plaintext && self.encryption.keys_file.is_empty() {
+            problems.push("encryption.delete_plaintext is set but encryption.keys_file is empty".to_string());
+        }
+
+        if let Err(e) = self.http_addr() {
+            problems.push(e);
+        }
+        if self.http.writer_stall_seconds == 0 {
+            problems.push("http.writer_stall_seconds must be at least 1".to_string());
+        }
+
+        problems
+    }
+
+    pub fn decode_mode(&self) -> Result<DecodeMode, String> {
+        match self.discord.decode_mode.as_str() {
+            "decrypt" => Ok(DecodeMode::Decrypt),
+            "decode" => Ok(DecodeMode::Decode),
+            x => Err(format!("discord.decode_mode: `{x}` is not supported (expected decrypt or decode)")),
+        }
+    }
+
+    /// Where to serve the HTTP endpoints, if anywhere.
+    pub fn http_addr(&self) -> Result<Option<SocketAddr>, String> {
+        match self.http.listen.as_str() {
+            "" => Ok(None),
+            x => x.parse().map(Some).map_err(|e| format!("http.listen: `{x}` is not a valid address: {e}")),
+        }
+    }
+
+    pub fn writer_stall_timeout(&self) -> Duration {
+        Duration::from_secs(self.http.writer_stall_seconds)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.discord.shutdown_timeout_seconds)
+    }
+
+    /// Only called once `problems` is empty, so what is left to fail is reading the keys file and setting up storage.
+    fn recorder_config(&self) -> Result<RecorderConfig, String> {
+        let recording = &self.recording;
+
+        let encryption = match self.encryption.keys_file.as_str() {
+            "" => None,
+            keys_file => {
+                let encryption = EncryptionConfig::load(Path::new(keys_file), self.encryption.delete_plaintext)
+                    .map_err(|e| format!("encryption.keys_file: {e}"))?;
+                Some(Arc::new(encryption))
+            }
+        };
+
+        Ok(RecorderConfig {
+            base_dir: recording.base_dir.clone(),
+            subdir_fmt: self.output.subdir_format.clone(),
+            track_name: TrackNameTemplate::parse(&self.output.track_name)?,
+            jitter_buffer_depth: recording.jitter_buffer_depth,
+            queue_depth: recording.queue_depth,
+            repair_lost_packets: recording.repair_lost_packets,
+            sync_policy: recording.sync_policy.parse()?,
+            segment_policy: SegmentPolicy {
+                max_duration: Some(Duration::from_secs(recording.segment_minutes * 60)).filter(|x| !x.is_zero()),
+                max_bytes: Some(recording.segment_mb * 1024 * 1024).filter(|x| *x > 0),
+            },
+            clip_history: Duration::from_secs(recording.clip_history_minutes * 60),
+            storage: self.storage_backend()?,
+            encryption,
+            capture_packets: recording.capture_packets,
+            capture_chat: recording.capture_chat,
+        })
+    }
+
+    fn storage_backend(&self) -> Result<Arc<dyn StorageBackend>, String> {
+        let storage = &self.storage;
+
+        match storage.backend.as_str() {
+            "local" => {
+                let dir = non_empty(&storage.local_dir).map(PathBuf::from).unwrap_or(self.recording.base_dir.clone());
+                Ok(Arc::new(LocalStorage::new(dir)))
+            }
+            "s3" => {
+                let s3 = &storage.s3;
+                Ok(Arc::new(S3Storage::new(S3Config {
+                    endpoint: non_empty(&s3.endpoint).map(|x| x.to_string()),
+                    region: s3.region.clone(),
+                    bucket: s3.bucket.clone(),
+                    access_key_id: s3.access_key_id.clone(),
+                    secret_access_key: s3.secret_access_key.clone(),
+                    prefix: s3.prefix.clone(),
+                    allow_http: s3.allow_http,
+                }).map_err(|e| format!("storage.s3: {e}"))?))
+            }
+            "sftp" => {
+                let sftp = &storage.sftp;
+                Ok(Arc::new(SftpStorage::new(SftpConfig {
+                    host: sftp.host.clone(),
+                    port: sftp.port,
+                    username: sftp.username.clone(),
+                    private_key: non_empty(&sftp.private_key).map(PathBuf::from),
+                    password: non_empty(&sftp.password).map(|x| x.to_string()),
+                    known_hosts: non_empty(&sftp.known_hosts).map(PathBuf::from),
+                    remote_dir: sftp.remote_dir.clone(),
+                }).map_err(|e| format!("storage.sftp: {e}"))?))
+            }
+            x => Err(format!("Unknown storage backend: {x}")),
+        }
+    }
+}
+
+fn non_empty(value: &str) -> Option<&str> {
+    Some(value).filter(|x| !x.is_empty())
+}
+
+/// Merges `overlay` into `base`, table by table.
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Table(base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Overrides settings from the legacy variables in `vars`, then from `DISRECORD_<SECTION>_<KEY>` variables.
+fn apply_env(config: &mut Value, vars: &HashMap<String, String>) -> Result<(), String> {
+    for &(var, path) in LEGACY_VARS {
+        if let Some(raw) = vars.get(var) {
+            let path = path.split('.').map(|x| x.to_string()).collect::<Vec<_>>();
+            set_value(config, &path, raw).map_err(|e| format!("{var}: {e}"))?;
+        }
+    }
+
+    let mut vars = vars.iter().filter(|(name, _)| name.starts_with(ENV_PREFIX) && **name != format!("{ENV_PREFIX}CONFIG")).collect::<Vec<_>>();
+    vars.sort();
+
+    for (var, raw) in vars {
+        let words = var[ENV_PREFIX.len()..].to_lowercase().split('_').map(|x| x.to_string()).collect::<Vec<_>>();
+        let path = resolve_path(config, &words).ok_or(format!("{var}: no such setting"))?;
+        set_value(config, &path, raw).map_err(|e| format!("{var}: {e}"))?;
+    }
+
+    Ok(())
+}
+
+/// Splits the words of a variable name into a path of keys, since keys may contain underscores themselves.
+fn resolve_path(value: &Value, words: &[String]) -> Option<Vec<String>> {
+    let table = value.as_table()?;
+
+    for end in (1..=words.len()).rev() {
+        let key = words[..end].join("_");
+        let Some(child) = table.get(&key) else {
+            continue;
+        };
+
+        if end == words.len() {
+            return (!child.is_table()).then(|| vec![key]);
+        }
+        if let Some(mut rest) = resolve_path(child, &words[end..]) {
+            rest.insert(0, key);
+            return Some(rest);
+        }
+    }
+
+    None
+}
+
+/// Sets a setting from the text of an environment variable, parsed as whatever type the setting already has.
+fn set_value(config: &mut Value, path: &[String], raw: &str) -> Result<(), String> {
+    let mut value = config;
+    for key in path {
+        value = value.as_table_mut().and_then(|x| x.get_mut(key)).ok_or("no such setting")?;
+    }
+
+    *value = match value {
+        Value::Boolean(_) => Value::Boolean(raw.parse().map_err(|_| format!("expected true or false, got `{raw}`"))?),
+        Value::Integer(_) => Value::Integer(raw.parse().map_err(|_| format!("expected a whole number, got `{raw}`"))?),
+        Value::Float(_) => Value::Float(raw.parse().map_err(|_| format!("expected a number, got `{raw}`"))?),
+        _ => Value::String(raw.to_string()),
+    };
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> Value {
+        Value::try_from(Config::default()).unwrap()
+    }
+
+    fn with_env(vars: &[(&str, &str)]) -> Result<Config, String> {
+        let mut value = defaults();
+        let vars = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        apply_env(&mut value, &vars)?;
+        value.try_into().map_err(|e: toml::de::Error| e.to_string())
+    }
+
+    fn valid() -> Config {
+        with_env(&[("DISRECORD_DISCORD_BOT_TOKEN", "token"), ("DISRECORD_DISCORD_APP_ID", "1")]).unwrap()
+    }
+
+    #[test]
+    fn env_vars_split_into_keys_with_underscores() {
+        let config = with_env(&[
+            ("DISRECORD_STORAGE_S3_ACCESS_KEY_ID", "key"),
+            ("DISRECORD_RECORDING_CLIP_HISTORY_MINUTES", "5"),
+            ("DISRECORD_HTTP_RESTART_ON_STALL", "true"),
+            ("DISRECORD_CONFIG", "ignored.toml"),
+        ]).unwrap();
+
+        assert_eq!(config.storage.s3.access_key_id, "key");
+        assert_eq!(config.recording.clip_history_minutes, 5);
+        assert!(config.http.restart_on_stall);
+    }
+
+    #[test]
+    fn env_vars_must_name_a_setting_of_the_right_type() {
+        let err = with_env(&[("DISRECORD_RECORDING_QUEUE_DEPTH", "lots")]).unwrap_err();
+        assert!(err.starts_with("DISRECORD_RECORDING_QUEUE_DEPTH: expected a whole number"), "{err}");
+
+        let err = with_env(&[("DISRECORD_HTTP_RESTART_ON_STALL", "yes")]).unwrap_err();
+        assert!(err.starts_with("DISRECORD_HTTP_RESTART_ON_STALL: expected true or false"), "{err}");
+
+        assert_eq!(with_env(&[("DISRECORD_FOO", "1")]).unwrap_err(), "DISRECORD_FOO: no such setting");
+        // A section on its own isn't a setting.
+        assert_eq!(with_env(&[("DISRECORD_STORAGE_S3", "1")]).unwrap_err(), "DISRECORD_STORAGE_S3: no such setting");
+    }
+
+    #[test]
+    fn legacy_vars_are_overridden_by_new_ones() {
+        let config = with_env(&[("BOT_TOKEN", "legacy"), ("S3_BUCKET", "bucket")]).unwrap();
+        assert_eq!(config.discord.bot_token, "legacy");
+        assert_eq!(config.storage.s3.bucket, "bucket");
+
+        let config = with_env(&[("BOT_TOKEN", "legacy"), ("DISRECORD_DISCORD_BOT_TOKEN", "new")]).unwrap();
+        assert_eq!(config.discord.bot_token, "new");
+    }
+
+    #[test]
+    fn file_settings_merge_over_defaults() {
+        let mut value = defaults();
+        merge(&mut value, Value::Table("[storage.s3]\nbucket = \"b\"\n[http]\nlisten = \"\"".parse().unwrap()));
+        let config: Config = value.try_into().unwrap();
+
+        assert_eq!(config.storage.s3.bucket, "b");
+        assert_eq!(config.storage.s3.region, "us-east-1");
+        assert_eq!(config.http.listen, "");
+        assert_eq!(config.http.writer_stall_seconds, 30);
+
+        let mut value = defaults();
+        merge(&mut value, Value::Table("[recording]\nbase_dirr = \"x\"".parse().unwrap()));
+        assert!(value.try_into::<Config>().unwrap_err().to_string().contains("unknown field `base_dirr`"));
+    }
+
+    #[test]
+    fn problems_are_all_reported() {
+        let problems = Config::default().problems();
+        assert!(problems.contains(&"discord.bot_token must be set".to_string()));
+        assert!(problems.contains(&"discord.app_id must be set".to_string()));
+
+        let mut config = valid();
+        assert_eq!(config.problems(), Vec::<String>::new());
+
+        config.recording.segment_minutes = u64::MAX / 30;
+        config.recording.segment_mb = u64::MAX / 1024;
+        config.storage.backend = "s3".to_string();
+        let problems = config.problems();
+        assert_eq!(problems.len(), 5, "{problems:?}");
+        assert!(problems.iter().any(|x| x.starts_with("recording.segment_minutes must be at most")));
+        assert!(problems.iter().any(|x| x.starts_with("recording.segment_mb must be at most")));
+        assert!(problems.contains(&"storage.s3.bucket must be set for the s3 backend".to_string()));
+    }
+
+    #[test]
+    fn storage_backend_is_built_on_load() {
+        let mut config = valid();
+        config.storage.backend = "s3".to_string();
+        config.storage.s3.bucket = "bucket".to_string();
+        config.storage.s3.access_key_id = "key".to_string();
+        config.storage.s3.secret_access_key = "secret".to_string();
+
+        let (_, recorder) = config.validated().unwrap();
+        assert!(format!("{:?}", recorder.storage).contains("bucket"));
+    }
+
+    #[test]
+    fn unreadable_keys_are_invalid_configuration() {
+        let keys_path = std::env::temp_dir().join(format!("disrecord_config_keys_{}", std::process::id()));
+        std::fs::write(&keys_path, "123 not-a-key\n").unwrap();
+
+        let mut config = valid();
+        config.encryption.keys_file = keys_path.to_string_lossy().to_string();
+        let err = config.validated().unwrap_err();
+        assert!(err.starts_with("encryption.keys_file: "), "{err}");
+
+        std::fs::remove_file(&keys_path).unwrap();
+    }
+}
\ No newline at end of file
@@ -1,6 +1,10 @@
 use crate::commands;
-use crate::recorder::recorder::Recorder;
-use serenity::all::{Command, Interaction};
+use crate::health::Health;
+use crate::metrics::CommandMetrics;
+use crate::presence::PresenceManager;
+use crate::shutdown::Shutdown;
+use disrecord::recorder::recorder::Recorder;
+use serenity::all::{Command, ConnectionStage, CreateInteractionResponse, CreateInteractionResponseMessage, Interaction, Message, ShardStageUpdateEvent, VoiceState};
 use serenity::prelude::TypeMapKey;
 use serenity::{
     async_trait,
@@ -8,9 +12,22 @@ use serenity::{
     model::gateway::Ready,
 };
 use std::sync::Arc;
+use std::time::Instant;
 
-impl TypeMapKey for Recorder {
-    type Value = Arc<Recorder>;
+impl TypeMapKey for PresenceManager {
+    type Value = Arc<PresenceManager>;
+}
+
+impl TypeMapKey for Shutdown {
+    type Value = Arc<Shutdown>;
+}
+
+impl TypeMapKey for CommandMetrics {
+    type Value = Arc<CommandMetrics>;
+}
+
+impl TypeMapKey for Health {
+    type Value = Arc<Health>;
 }
 
 pub struct Events;
@@ -20,23 +37,75 @@ impl EventHandler for Events {
     async fn ready(&self, ctx: Context, ready: Ready) {
         info!("{} is connected!", ready.user.name);
 
-        Command::set_global_commands(&ctx.http,
-                                     vec![
-                                         commands::start::register(),
-                                         commands::finish::register(),
-                                         commands::rejoin::register(),
-                                     ]
-        ).await.expect("Failed to register global commands!");
+        let shutdown = Shutdown::get(&ctx).await.expect("Shutdown doesn't exist!");
+        shutdown.set_context(&ctx);
+
+        let health = Health::get(&ctx).await.expect("Health doesn't exist!");
+        health.set_connected(ctx.shard_id, true);
+
+        let recorder = Recorder::get(&ctx).await.expect("RecordManager doesn't exist!");
+        let clip_minutes = recorder.config().clip_history.as_secs() / 60;
+
+        let mut global_commands = vec![
+            commands::start::register(),
+            commands::finish::register(),
+            commands::rejoin::register(),
+            commands::recordings::register(),
+        ];
+
+        // Without any history kept there is nothing to clip.
+        if clip_minutes > 0 {
+            global_commands.push(commands::clip::register(clip_minutes));
+        }
+
+        Command::set_global_commands(&ctx.http, global_commands).await.expect("Failed to register global commands!");
+    }
+
+    async fn shard_stage_update(&self, ctx: Context, event: ShardStageUpdateEvent) {
+        debug!("Shard {} is now {}", event.shard_id, event.new);
+
+        let health = Health::get(&ctx).await.expect("Health doesn't exist!");
+        health.set_connected(event.shard_id, event.new == ConnectionStage::Connected);
+    }
+
+    async fn message(&self, ctx: Context, msg: Message) {
+        if let Some(guild_id) = msg.guild_id {
+            let recorder = Recorder::get(&ctx).await.expect("RecordManager doesn't exist!");
+            recorder.log_chat(&ctx, guild_id, &msg).await;
+        }
+    }
+
+    async fn voice_state_update(&self, ctx: Context, old: Option<VoiceState>, new: VoiceState) {
+        let recorder = Recorder::get(&ctx).await.expect("RecordManager doesn't exist!");
+        recorder.log_voice_state(&ctx, old.as_ref(), &new).await;
     }
 
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
         if let Interaction::Command(command) = interaction {
+            let shutdown = Shutdown::get(&ctx).await.expect("Shutdown doesn't exist!");
+            if shutdown.is_shutting_down() {
+                let resp = CreateInteractionResponseMessage::new()
+                    .content("The bot is restarting, try again in a moment.")
+                    .ephemeral(true);
+
+                command.create_response(&ctx, CreateInteractionResponse::Message(resp)).await.unwrap_or_else(|e| {
+                    error!("Error responding to the interaction: {e:?}");
+                });
+                return;
+            }
+
+            let start = Instant::now();
             match command.data.name.as_str() {
                 commands::start::NAME => commands::start::run(&ctx, &command).await,
                 commands::finish::NAME => commands::finish::run(&ctx, &command).await,
                 commands::rejoin::NAME => commands::rejoin::run(&ctx, &command).await,
-                _ => {}
+                commands::recordings::NAME => commands::recordings::run(&ctx, &command).await,
+                commands::clip::NAME => commands::clip::run(&ctx, &command).await,
+                _ => return,
             }
+
+            let metrics = CommandMetrics::get(&ctx).await.expect("CommandMetrics doesn't exist!");
+            metrics.record(&command.data.name, start.elapsed());
         }
     }
 }
\ No newline at end of file
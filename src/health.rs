@@ -0,0 +1,30 @@
+use serenity::all::{Context, ShardId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Tracks which shards are connected to the gateway, for `/readyz`.
+#[derive(Debug, Default)]
+pub struct Health {
+    shards: Mutex<HashMap<ShardId, bool>>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn get(ctx: &Context) -> Option<Arc<Self>> {
+        let data = ctx.data.read().await;
+        data.get::<Self>().cloned()
+    }
+
+    pub fn set_connected(&self, shard_id: ShardId, connected: bool) {
+        self.shards.lock().unwrap().insert(shard_id, connected);
+    }
+
+    /// Whether `ready` has fired and every shard is still connected.
+    pub fn gateway_connected(&self) -> bool {
+        let shards = self.shards.lock().unwrap();
+        !shards.is_empty() && shards.values().all(|x| *x)
+    }
+}
\ No newline at end of file
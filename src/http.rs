@@ -0,0 +1,168 @@
+use crate::health::Health;
+use crate::metrics::CommandMetrics;
+use crate::shutdown::Shutdown;
+use disrecord::recorder::recorder::Recorder;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Requests are only a request line and a few headers, anything bigger is refused.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the HTTP endpoints report on.
+#[derive(Clone, Debug)]
+pub struct HttpState {
+    pub recorder: Arc<Recorder>,
+    pub commands: Arc<CommandMetrics>,
+    pub health: Arc<Health>,
+    pub shutdown: Arc<Shutdown>,
+    /// How long a writer can spend on one update before it counts as stalled.
+    pub stall_after: Duration,
+    /// Whether stalled writers also fail `/healthz`, rather than only `/readyz`.
+    pub restart_on_stall: bool,
+}
+
+/// The outcome of one health check.
+struct Check {
+    name: &'static str,
+    /// Whether a failure should also fail `/healthz`, rather than only `/readyz`.
+    liveness: bool,
+    result: Result<(), String>,
+}
+
+/// Serves `/metrics`, `/healthz` and `/readyz` until the process exits.
+pub async fn serve(addr: SocketAddr, state: HttpState) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to listen on {addr}: {e}");
+            return;
+        }
+    };
+
+    info!("Serving metrics and health checks on http://{addr}");
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to accept HTTP connection: {e}");
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &state).await {
+                debug!("HTTP request from {peer} failed: {e}");
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, state: &HttpState) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+
+    // Only the request line matters, but the headers are read so that the client isn't reset while still sending them.
+    while !request.windows(4).any(|x| x == b"\r\n\r\n") {
+        let read = match tokio::time::timeout(READ_TIMEOUT, stream.read(&mut buf)).await {
+            Ok(x) => x?,
+            Err(_) => return Ok(()),
+        };
+        if read == 0 {
+            return Ok(());
+        }
+
+        request.extend_from_slice(&buf[..read]);
+        if request.len() > MAX_REQUEST_SIZE {
+            return respond(&mut stream, "431 Request Header Fields Too Large", "text/plain", "Request too large\n").await;
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+    let path = path.split_once('?').map(|x| x.0).unwrap_or(path);
+
+    if method != "GET" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", "Method not allowed\n").await;
+    }
+
+    match path {
+        "/metrics" => {
+            let mut body = String::new();
+            state.recorder.write_metrics(&mut body);
+            state.commands.write(&mut body);
+            respond(&mut stream, "200 OK", "text/plain; version=0.0.4; charset=utf-8", &body).await
+        }
+        "/healthz" | "/readyz" => {
+            let checks = run_checks(state).await;
+            let failed = checks.iter().any(|x| x.result.is_err() && (x.liveness || path == "/readyz"));
+
+            let mut body = String::new();
+            for check in &checks {
+                match &check.result {
+                    Ok(()) => _ = writeln!(body, "{}: ok", check.name),
+                    Err(e) => _ = writeln!(body, "{}: {e}", check.name),
+                }
+            }
+
+            let status = if failed { "503 Service Unavailable" } else { "200 OK" };
+            respond(&mut stream, status, "text/plain; charset=utf-8", &body).await
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "Not found\n").await,
+    }
+}
+
+/// Runs every check. Only stopped writers make the bot unhealthy, and stuck ones if `restart_on_stall` is set;
+/// the rest only make it unready.
+async fn run_checks(state: &HttpState) -> Vec<Check> {
+    let gateway = if state.shutdown.is_shutting_down() {
+        Err("shutting down".to_string())
+    } else if state.health.gateway_connected() {
+        Ok(())
+    } else {
+        Err("not connected".to_string())
+    };
+
+    let base_dir = state.recorder.check_base_dir().await.map_err(|e| e.to_string());
+
+    let calls = state.recorder.health();
+
+    let stopped = calls.iter().filter(|x| !x.task_alive).map(|x| x.guild_id.to_string()).collect::<Vec<_>>();
+    let writer_tasks = if stopped.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("stopped in guild(s) {}", stopped.join(", ")))
+    };
+
+    let stalled = calls.iter()
+        .filter_map(|x| x.busy_for.filter(|x| *x >= state.stall_after).map(|busy_for| format!("{} ({}s)", x.guild_id, busy_for.as_secs())))
+        .collect::<Vec<_>>();
+    let writer_stalls = if stalled.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("stuck on an update in guild(s) {}", stalled.join(", ")))
+    };
+
+    vec![
+        Check { name: "gateway", liveness: false, result: gateway },
+        Check { name: "base_dir", liveness: false, result: base_dir },
+        Check { name: "writer_tasks", liveness: true, result: writer_tasks },
+        Check { name: "writer_stalls", liveness: state.restart_on_stall, result: writer_stalls },
+    ]
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> std::io::Result<()> {
+    let head = format!("HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
\ No newline at end of file
@@ -0,0 +1,19 @@
+//! The recording engine behind the disrecord bot, for embedding in other serenity/songbird bots.
+//!
+//! [`Recorder`](recorder::recorder::Recorder) is the entry point: it joins a voice channel through songbird and
+//! feeds what it hears to a [`Writer`](recorder::writer::Writer), which keeps one
+//! [`CallWriter`](recorder::writer::call_writer::CallWriter) per guild and one
+//! [`StreamWriter`](recorder::writer::StreamWriter) per speaker. Tracks are written as Ogg Opus by the
+//! [`muxer`](recorder::writer::muxer) without re-encoding, then zipped and handed to a
+//! [`StorageBackend`](recorder::storage::StorageBackend) when the recording is finished.
+//!
+//! Everything is configured through [`RecorderConfig`](recorder::RecorderConfig). The writer can also be fed
+//! without Discord from any [`VoiceSource`](recorder::writer::source::VoiceSource), such as a packet capture.
+
+#![warn(missing_docs)]
+
+#[macro_use]
+extern crate log;
+
+/// Recording voice calls: receiving, writing, zipping and storing them.
+pub mod recorder;
\ No newline at end of file
@@ -0,0 +1,152 @@
+use crate::config::LoggingConfig;
+use fern::colors::{Color, ColoredLevelConfig};
+use fern::FormatCallback;
+use log::{LevelFilter, Record};
+use std::fmt::Arguments;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub fn setup_logger(config: &LoggingConfig) {
+    let colors_line = ColoredLevelConfig::new()
+        .error(Color::BrightRed)
+        .warn(Color::BrightYellow)
+        .info(Color::BrightWhite)
+        .debug(Color::White)
+        .trace(Color::BrightBlack);
+
+    let colors_level = colors_line.clone()
+        .error(Color::Red)
+        .warn(Color::Yellow)
+        .info(Color::BrightGreen)
+        .debug(Color::BrightCyan)
+        .trace(Color::Black);
+
+    // Already checked when the config was loaded.
+    let log_level = LevelFilter::from_str(&config.level).unwrap_or(LevelFilter::Trace);
+    let log_level_all = LevelFilter::from_str(&config.level_all).unwrap_or(LevelFilter::Warn);
+
+    let color = config.color;
+    let console = fern::Dispatch::new()
+        .format(move |out, message, record| {
+            if color {
+                out.finish(format_args!(
+                    "{color_line}[{date}][{target}][{level}{color_line}] {message}\x1B[0m",
+                    color_line = format_args!(
+                        "\x1B[{}m",
+                        colors_line.get_color(&record.level()).to_fg_str()
+                    ),
+                    date = chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                    target = record.target(),
+                    level = colors_level.color(record.level()),
+                    message = message,
+                ));
+            } else {
+                finish_plain(out, message, record);
+            }
+        })
+        .chain(std::io::stdout());
+
+    let mut dispatch = fern::Dispatch::new()
+        .level(log_level_all)
+        .level_for("disrecord", log_level)
+        .chain(console);
+
+    if !config.file.is_empty() {
+        match RotatingFile::open(PathBuf::from(&config.file), config.max_size_mb * 1024 * 1024, config.keep_files) {
+            Ok(logfile) => {
+                let file = fern::Dispatch::new()
+                    .format(finish_plain)
+                    .chain(Box::new(logfile) as Box<dyn Write + Send>);
+                dispatch = dispatch.chain(file);
+            }
+            Err(e) => {
+                println!("Error setting up logger: {e}")
+            }
+        }
+    }
+
+    dispatch
+        .apply()
+        .unwrap();
+}
+
+fn finish_plain(out: FormatCallback, message: &Arguments, record: &Record) {
+    out.finish(format_args!(
+        "[{date}][{target}][{level}] {message}",
+        date = chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+        target = record.target(),
+        level = record.level(),
+    ));
+}
+
+/// A log file that is moved to `<file>.1` once it grows past `max_bytes`, shifting older ones up to `<file>.<keep>`.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    /// 0 to never rotate.
+    max_bytes: u64,
+    keep: u32,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, keep: u32) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            file,
+            len,
+            max_bytes,
+            keep,
+        })
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if Path::exists(&from) {
+                    std::fs::rename(from, self.rotated(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.max_bytes > 0 && self.len > 0 && self.len + buf.len() as u64 > self.max_bytes {
+            // Keep logging to the old file rather than losing the message.
+            if let Err(e) = self.rotate() {
+                eprintln!("Failed to rotate {}: {e}", self.path.display());
+            }
+        }
+
+        let written = self.file.write(buf)?;
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
\ No newline at end of file
@@ -5,133 +5,211 @@ extern crate log;
 
 mod discord;
 mod commands;
-mod recorder;
-
-use crate::recorder::RecorderConfig;
-use fern::colors::{Color, ColoredLevelConfig};
-use log::LevelFilter;
-use recorder::recorder::Recorder;
+mod config;
+mod health;
+mod http;
+mod logging;
+mod metrics;
+mod presence;
+mod shutdown;
+
+use config::{Config, LoggingConfig};
+use disrecord::recorder::{RecorderConfig, SegmentPolicy};
+use disrecord::recorder::recorder::Recorder;
+use disrecord::recorder::durability::SyncPolicy;
+use disrecord::recorder::storage::local::LocalStorage;
+use health::Health;
+use http::HttpState;
+use metrics::CommandMetrics;
+use disrecord::recorder::track_name::TrackNameTemplate;
+use presence::PresenceManager;
 use serenity::all::ApplicationId;
+use shutdown::Shutdown;
 use serenity::prelude::GatewayIntents;
 use serenity::Client;
-use songbird::driver::DecodeMode;
-use songbird::{Config, SerenityInit};
+use songbird::SerenityInit;
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+
+const USAGE: &str = "Usage: disrecord [decrypt <identity file> <input.age> [output] | replay <capture.dump> [output dir]]";
 
 fn main() {
     dotenv::dotenv().ok();
-    setup_logger();
 
-    bot()
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(|x| x.as_str()) {
+        None => {
+            let (config, record_config) = match Config::load() {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("Invalid configuration:\n{e}");
+                    std::process::exit(1);
+                }
+            };
+
+            logging::setup_logger(&config.logging);
+            bot(config, record_config)
+        }
+        Some("decrypt") => decrypt(&args[1..]),
+        Some("replay") => {
+            logging::setup_logger(&LoggingConfig::default());
+            replay(&args[1..])
+        }
+        Some(_) => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    }
 }
 
-#[tokio::main]
-async fn bot() {
-    let bot_token = env::var("BOT_TOKEN").expect("Expected a BOT_TOKEN in the environment");
+fn decrypt(args: &[String]) {
+    let (identity_file, input, output) = match args {
+        [identity_file, input] => (identity_file, input, None),
+        [identity_file, input, output] => (identity_file, input, Some(Path::new(output))),
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    };
 
-    let app_id: ApplicationId = env::var("APP_ID").expect("Expected an APP_ID in the environment")
-        .parse().expect("APP_ID is not a valid ID");
+    match disrecord::recorder::encryption::decrypt_file(Path::new(identity_file), Path::new(input), output) {
+        Ok(path) => println!("Decrypted to {}", path.display()),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
 
-    let intents = GatewayIntents::non_privileged();
+/// Rebuilds a recording from a packet capture, with the same settings every time so that the tracks are reproducible.
+#[tokio::main]
+async fn replay(args: &[String]) {
+    let (input, output) = match args {
+        [input] => (input.as_str(), "replay"),
+        [input, output] => (input.as_str(), output.as_str()),
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    };
 
-    let songbird_config = Config::default()
-        .decode_mode(DecodeMode::Decrypt);
+    let base_dir = PathBuf::from(output);
+    let defaults = Config::default();
 
     let record_config = RecorderConfig {
-        base_dir: PathBuf::from("recordings"),
-        subdir_fmt: "%Y_%m_%d_%H_%M_%S".to_string(),
+        base_dir: base_dir.clone(),
+        subdir_fmt: defaults.output.subdir_format,
+        track_name: TrackNameTemplate::parse(&defaults.output.track_name).expect("Invalid track name template"),
+        jitter_buffer_depth: defaults.recording.jitter_buffer_depth,
+        queue_depth: defaults.recording.queue_depth,
+        repair_lost_packets: false,
+        sync_policy: SyncPolicy::Never,
+        segment_policy: SegmentPolicy::default(),
+        clip_history: Duration::ZERO,
+        storage: Arc::new(LocalStorage::new(base_dir)),
+        encryption: None,
+        capture_packets: false,
+        capture_chat: false,
+    };
+
+    let recorder = Recorder::new(record_config);
+
+    let summary = match recorder.replay(Path::new(input)).await {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(e) = &summary.first_error {
+        eprintln!("{} error(s) while writing, first: {e}", summary.write_errors);
+    }
+
+    match summary.zip_rx.await {
+        Ok(Ok(stored)) => println!("Replayed {} to {}", summary.id, stored.location),
+        Ok(Err(e)) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        Err(_) => {
+            eprintln!("Failed to package recording!");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[tokio::main]
+async fn bot(config: Config, record_config: RecorderConfig) {
+    let app_id = ApplicationId::new(config.discord.app_id);
+
+    // Message content is privileged, and must also be enabled for the bot in the developer portal.
+    let intents = if config.recording.capture_chat {
+        GatewayIntents::non_privileged() | GatewayIntents::MESSAGE_CONTENT
+    } else {
+        GatewayIntents::non_privileged()
     };
 
+    let songbird_config = songbird::Config::default()
+        .decode_mode(config.decode_mode().expect("Invalid decode mode"));
+
     let recorder = Arc::new(Recorder::new(record_config));
+    let shutdown = Arc::new(Shutdown::new());
+    let command_metrics = Arc::new(CommandMetrics::new());
+    let health = Arc::new(Health::new());
+
+    let shutdown_timeout = config.shutdown_timeout();
 
-    let mut client = Client::builder(&bot_token, intents)
+    let mut client = Client::builder(&config.discord.bot_token, intents)
         .event_handler(discord::Events)
         .application_id(app_id)
         .register_songbird_from_config(songbird_config)
-        .type_map_insert::<Recorder>(recorder)
+        .type_map_insert::<Recorder>(recorder.clone())
+        .type_map_insert::<Shutdown>(shutdown.clone())
+        .type_map_insert::<CommandMetrics>(command_metrics.clone())
+        .type_map_insert::<Health>(health.clone())
         .await
         .expect("Error creating client!");
 
-    info!("Starting Disrecord...");
-
-    if let Err(why) = client.start_autosharded().await {
-        error!("Client error: {:?}", why);
+    let presence = Arc::new(PresenceManager::new(client.shard_manager.clone()));
+    client.data.write().await.insert::<PresenceManager>(presence);
+
+    if let Some(addr) = config.http_addr().expect("Invalid HTTP address") {
+        let state = HttpState {
+            recorder: recorder.clone(),
+            commands: command_metrics,
+            health,
+            shutdown: shutdown.clone(),
+            stall_after: config.writer_stall_timeout(),
+            restart_on_stall: config.http.restart_on_stall,
+        };
+        tokio::spawn(http::serve(addr, state));
     }
 
-    info!("Goodbye!")
-}
+    info!("Starting Disrecord...");
 
-fn setup_logger() {
-    let colors_line = ColoredLevelConfig::new()
-        .error(Color::BrightRed)
-        .warn(Color::BrightYellow)
-        .info(Color::BrightWhite)
-        .debug(Color::White)
-        .trace(Color::BrightBlack);
-
-    let colors_level = colors_line.clone()
-        .error(Color::Red)
-        .warn(Color::Yellow)
-        .info(Color::BrightGreen)
-        .debug(Color::BrightCyan)
-        .trace(Color::Black);
-
-    let log_level = if let Ok(level) = env::var("LOG_LEVEL") {
-        match level.as_str() {
-            "error" => LevelFilter::Error,
-            "warn" => LevelFilter::Warn,
-            "info" => LevelFilter::Info,
-            "debug" => LevelFilter::Debug,
-            "trace" => LevelFilter::Trace,
-            _ => panic!("Unknown log level: {}", level),
-        }
-    } else {
-        LevelFilter::Trace
-    };
+    let shard_manager = client.shard_manager.clone();
+    let mut client_task = tokio::spawn(async move { client.start_autosharded().await });
 
-    let log_level_all = if let Ok(level) = env::var("LOG_LEVEL_ALL") {
-        match level.as_str() {
-            "error" => LevelFilter::Error,
-            "warn" => LevelFilter::Warn,
-            "info" => LevelFilter::Info,
-            "debug" => LevelFilter::Debug,
-            "trace" => LevelFilter::Trace,
-            _ => panic!("Unknown log level: {}", level),
+    tokio::select! {
+        res = &mut client_task => {
+            match res {
+                Ok(Err(why)) => error!("Client error: {:?}", why),
+                Err(e) => error!("Client task failed: {e:?}"),
+                Ok(Ok(())) => {}
+            }
         }
-    } else {
-        LevelFilter::Warn
-    };
+        _ = shutdown::wait_for_signal() => {
+            if tokio::time::timeout(shutdown_timeout, shutdown.run(recorder)).await.is_err() {
+                warn!("Timed out after {shutdown_timeout:?} waiting for recordings to finish!");
+            }
 
-    let mut dispatch = fern::Dispatch::new()
-        .format(move |out, message, record| {
-            out.finish(format_args!(
-                "{color_line}[{date}][{target}][{level}{color_line}] {message}\x1B[0m",
-                color_line = format_args!(
-                    "\x1B[{}m",
-                    colors_line.get_color(&record.level()).to_fg_str()
-                ),
-                date = chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                target = record.target(),
-                level = colors_level.color(record.level()),
-                message = message,
-            ));
-        })
-        .level(log_level_all)
-        .level_for("disrecord", log_level)
-        .chain(std::io::stdout());
-
-    match fern::log_file("disrecord.log") {
-        Ok(logfile) => {
-            dispatch = dispatch.chain(logfile);
-        }
-        Err(e) => {
-            println!("Error setting up logger: {e}")
+            shard_manager.shutdown_all().await;
+            _ = client_task.await;
         }
     }
 
-    dispatch
-        .apply()
-        .unwrap();
+    info!("Goodbye!")
 }
\ No newline at end of file
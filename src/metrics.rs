@@ -0,0 +1,40 @@
+use disrecord::recorder::metrics::{write_header, Histogram, DURATION_BUCKETS};
+use serenity::all::Context;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How many times each slash command has been run, and how long it took to answer.
+#[derive(Debug, Default)]
+pub struct CommandMetrics {
+    commands: Mutex<BTreeMap<String, Arc<Histogram>>>,
+}
+
+impl CommandMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn get(ctx: &Context) -> Option<Arc<Self>> {
+        let data = ctx.data.read().await;
+        data.get::<Self>().cloned()
+    }
+
+    pub fn record(&self, command: &str, took: Duration) {
+        let histogram = self.commands.lock().unwrap()
+            .entry(command.to_string())
+            .or_insert_with(|| Arc::new(Histogram::new(DURATION_BUCKETS)))
+            .clone();
+
+        histogram.observe(took.as_secs_f64());
+    }
+
+    pub fn write(&self, out: &mut String) {
+        let commands = self.commands.lock().unwrap().clone();
+
+        write_header(out, "disrecord_command_duration_seconds", "histogram", "Time taken to run each slash command.");
+        for (command, histogram) in commands {
+            histogram.write(out, "disrecord_command_duration_seconds", &[("command", &command)]);
+        }
+    }
+}
\ No newline at end of file
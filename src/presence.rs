@@ -0,0 +1,115 @@
+use serenity::all::{ActivityData, Context, GuildId, OnlineStatus, ShardManager};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Put in front of the bot's nickname in guilds that are recording.
+const RECORDING_PREFIX: &str = "🔴 ";
+
+/// The nickname the bot had before it was changed, once it has been. Locked while the nickname is being changed.
+type SavedNickname = Arc<tokio::sync::Mutex<Option<Option<String>>>>;
+
+/// Keeps the bot's presence in line with the recordings running across every guild.
+///
+/// The nickname is only changed in guilds that are recording, while the global activity shows how many are.
+#[derive(Debug)]
+pub struct PresenceManager {
+    recording: Mutex<HashSet<GuildId>>,
+    /// The nickname the bot had in each recording guild before it was changed, restored once the recording stops.
+    /// Added along with the guild to `recording`, so that a `stop` always finds it and waits for `start` to finish.
+    previous_nicknames: Mutex<HashMap<GuildId, SavedNickname>>,
+    /// Presence is per shard, so the activity has to be sent to every one of them.
+    shard_manager: Arc<ShardManager>,
+}
+
+impl PresenceManager {
+    pub fn new(shard_manager: Arc<ShardManager>) -> Self {
+        Self {
+            recording: Mutex::new(HashSet::new()),
+            previous_nicknames: Mutex::new(HashMap::new()),
+            shard_manager,
+        }
+    }
+
+    pub async fn get(ctx: &Context) -> Option<Arc<Self>> {
+        let data = ctx.data.read().await;
+        data.get::<Self>().cloned()
+    }
+
+    /// Marks a guild as recording.
+    pub async fn start(&self, ctx: &Context, guild_id: GuildId) {
+        let (saved, sessions) = {
+            let mut recording = self.recording.lock().unwrap();
+            let saved = if recording.insert(guild_id) {
+                let saved = SavedNickname::default();
+                let guard = saved.clone().try_lock_owned().expect("A new lock is always free");
+                self.previous_nicknames.lock().unwrap().insert(guild_id, saved);
+                Some(guard)
+            } else {
+                None
+            };
+            (saved, recording.len())
+        };
+        self.update_activity(sessions).await;
+
+        let mut saved = match saved {
+            Some(x) => x,
+            None => return,
+        };
+
+        let bot_user = ctx.cache.current_user().clone();
+        let previous = match guild_id.member(ctx, bot_user.id).await {
+            // A nickname that is still marked is left over from a recording that never got to restore it.
+            Ok(member) => member.nick
+                .map(|x| x.strip_prefix(RECORDING_PREFIX).map(|x| x.to_string()).unwrap_or(x))
+                .filter(|x| x != bot_user.display_name()),
+            Err(e) => {
+                // Without knowing the current nickname it couldn't be put back, so leave it alone.
+                warn!("[{guild_id}] Failed to read nickname: {e:?}");
+                return;
+            }
+        };
+
+        let name = previous.as_deref().unwrap_or(bot_user.display_name());
+        match guild_id.edit_nickname(ctx, Some(format!("{RECORDING_PREFIX}{name}").as_str())).await {
+            Ok(_) => *saved = Some(previous),
+            Err(e) => warn!("[{guild_id}] Failed to set nickname: {e:?}"),
+        }
+    }
+
+    /// Marks a guild as no longer recording. Does nothing if it wasn't.
+    pub async fn stop(&self, ctx: &Context, guild_id: GuildId) {
+        let (saved, sessions) = {
+            let mut recording = self.recording.lock().unwrap();
+            recording.remove(&guild_id);
+            (self.previous_nicknames.lock().unwrap().remove(&guild_id), recording.len())
+        };
+        self.update_activity(sessions).await;
+
+        let saved = match saved {
+            Some(x) => x,
+            None => return,
+        };
+
+        // Waits for a `start` that is still changing the nickname.
+        let previous = match saved.lock().await.take() {
+            Some(x) => x,
+            None => return,
+        };
+
+        guild_id.edit_nickname(ctx, previous.as_deref()).await.unwrap_or_else(|e| {
+            warn!("[{guild_id}] Failed to restore nickname: {e}");
+        });
+    }
+
+    async fn update_activity(&self, sessions: usize) {
+        let (activity, status) = match sessions {
+            0 => (None, OnlineStatus::Online),
+            1 => (Some(ActivityData::custom("Recording 1 session")), OnlineStatus::DoNotDisturb),
+            n => (Some(ActivityData::custom(format!("Recording {n} sessions"))), OnlineStatus::DoNotDisturb),
+        };
+
+        for runner in self.shard_manager.runners.lock().await.values() {
+            runner.runner_tx.set_presence(activity.clone(), status);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,198 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use sha2::{Digest, Sha256};
+use crate::recorder::encryption::ENCRYPTED_EXT;
+use crate::recorder::error::RecorderError;
+
+/// Name of the manifest written into every recording directory, in `sha256sum` format.
+pub const CHECKSUMS_FILE: &str = "SHA256SUMS";
+/// Extension of the single-file digests written next to the zip (and its encrypted copy).
+pub const DIGEST_EXT: &str = "sha256";
+
+/// Files that are produced after the manifest and so can't be listed in it.
+const EXCLUDED_EXTS: [&str; 4] = ["zip", "age", "part", DIGEST_EXT];
+
+/// Whether a file listed in a checksum file still matches it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChecksumStatus {
+    /// The file's digest matches.
+    Ok,
+    /// The file has changed since the recording was finished.
+    Mismatch,
+    /// The file is gone.
+    Missing,
+    /// Missing, but an encrypted copy of the recording exists, so it was removed on purpose.
+    DeletedAfterEncryption,
+}
+
+/// The result of checking one file listed in a checksum file.
+#[derive(Clone, Debug)]
+pub struct ChecksumResult {
+    /// Name of the file, relative to the recording's directory.
+    pub file_name: String,
+    /// What was found.
+    pub status: ChecksumStatus,
+}
+
+/// Hex-encoded SHA-256 of a file, read in chunks.
+pub fn sha256_file(path: &Path) -> Result<String, RecorderError> {
+    let mut file = File::open(path).map_err(|e| RecorderError::io("open", path, e))?;
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).map_err(|e| RecorderError::io("read", path, e))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    let mut hex = String::with_capacity(64);
+    for byte in hasher.finalize() {
+        write!(hex, "{byte:02x}").unwrap();
+    }
+
+    Ok(hex)
+}
+
+/// Writes `SHA256SUMS` covering every file in `dir` apart from the zip and other later outputs.
+pub fn write_checksums(dir: &Path) -> Result<PathBuf, RecorderError> {
+    let entries = std::fs::read_dir(dir).map_err(|e| RecorderError::io("read", dir, e))?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| RecorderError::io("read", dir, e))?;
+        let path = entry.path();
+
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let excluded = path.extension().is_some_and(|x| EXCLUDED_EXTS.iter().any(|ext| x == *ext));
+        if file_name == CHECKSUMS_FILE || excluded || !path.is_file() {
+            continue;
+        }
+
+        files.push((file_name, path));
+    }
+    files.sort();
+
+    let mut manifest = String::new();
+    for (file_name, path) in files {
+        manifest += &format!("{}  {file_name}\n", sha256_file(&path)?);
+    }
+
+    let manifest_path = dir.join(CHECKSUMS_FILE);
+    std::fs::write(&manifest_path, manifest).map_err(|e| RecorderError::io("write", &manifest_path, e))?;
+
+    Ok(manifest_path)
+}
+
+/// Writes `<file>.sha256` next to `path`, returning the digest.
+pub fn write_digest_file(path: &Path) -> Result<String, RecorderError> {
+    let digest = sha256_file(path)?;
+    let file_name = path.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+
+    let mut digest_path = path.as_os_str().to_owned();
+    digest_path.push(".");
+    digest_path.push(DIGEST_EXT);
+
+    std::fs::write(&digest_path, format!("{digest}  {file_name}\n")).map_err(|e| RecorderError::io("write", &digest_path, e))?;
+
+    Ok(digest)
+}
+
+/// Re-hashes every file listed in the recording's `SHA256SUMS` and any `.sha256` files next to the zip.
+/// When the recording was encrypted, plaintext files that are gone are reported as deleted rather than missing.
+pub fn verify_checksums(dir: &Path) -> Result<Vec<ChecksumResult>, RecorderError> {
+    let mut lists = vec![dir.join(CHECKSUMS_FILE)];
+    let mut encrypted = false;
+
+    let entries = std::fs::read_dir(dir).map_err(|e| RecorderError::io("read", dir, e))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_some_and(|x| x == DIGEST_EXT) {
+            lists.push(path);
+        } else if path.extension().is_some_and(|x| x == ENCRYPTED_EXT) {
+            encrypted = true;
+        }
+    }
+
+    let mut results = Vec::new();
+    for list in lists {
+        let contents = std::fs::read_to_string(&list).map_err(|e| RecorderError::io("read", &list, e))?;
+
+        for line in contents.lines().filter(|x| !x.trim().is_empty()) {
+            let invalid = || RecorderError::Checksum {
+                path: list.clone(),
+                line: line.to_string(),
+            };
+
+            let (expected, file_name) = line.split_once("  ").ok_or_else(invalid)?;
+
+            // Never follow a listed name outside of the recording directory.
+            if file_name.contains(['/', '\\']) || file_name == ".." {
+                return Err(invalid());
+            }
+
+            let path = dir.join(file_name);
+            let is_encrypted = path.extension().is_some_and(|x| x == ENCRYPTED_EXT);
+            let status = if !path.is_file() && encrypted && !is_encrypted {
+                ChecksumStatus::DeletedAfterEncryption
+            } else if !path.is_file() {
+                ChecksumStatus::Missing
+            } else if sha256_file(&path)? == expected {
+                ChecksumStatus::Ok
+            } else {
+                ChecksumStatus::Mismatch
+            };
+
+            results.push(ChecksumResult {
+                file_name: file_name.to_string(),
+                status,
+            });
+        }
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_plaintext_deleted_after_encryption() {
+        let dir = std::env::temp_dir().join(format!("disrecord_checksums_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        std::fs::write(dir.join("alice.opus"), b"alice").unwrap();
+        std::fs::write(dir.join("metadata.json"), b"{}").unwrap();
+        write_checksums(&dir).unwrap();
+
+        std::fs::write(dir.join("session.zip"), b"zip").unwrap();
+        write_digest_file(&dir.join("session.zip")).unwrap();
+        std::fs::write(dir.join("session.zip.age"), b"encrypted").unwrap();
+        write_digest_file(&dir.join("session.zip.age")).unwrap();
+
+        for file_name in ["alice.opus", "metadata.json", "session.zip"] {
+            std::fs::remove_file(dir.join(file_name)).unwrap();
+        }
+
+        let mut results = verify_checksums(&dir).unwrap().into_iter().map(|x| (x.file_name, x.status)).collect::<Vec<_>>();
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(results, vec![
+            ("alice.opus".to_string(), ChecksumStatus::DeletedAfterEncryption),
+            ("metadata.json".to_string(), ChecksumStatus::DeletedAfterEncryption),
+            ("session.zip".to_string(), ChecksumStatus::DeletedAfterEncryption),
+            ("session.zip.age".to_string(), ChecksumStatus::Ok),
+        ]);
+
+        // Without the encrypted copy, the same files are simply missing.
+        std::fs::remove_file(dir.join("session.zip.age")).unwrap();
+        let results = verify_checksums(&dir).unwrap();
+        assert!(results.iter().all(|x| x.status == ChecksumStatus::Missing));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
\ No newline at end of file
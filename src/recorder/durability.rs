@@ -0,0 +1,122 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::recorder::error::RecorderError;
+
+/// When tracks are flushed to disk with `fsync` while recording.
+///
+/// Syncs happen as Ogg pages are written, so an interval is only checked once per page (roughly every 4 s of speech).
+/// Every track is always synced when it is finished.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncPolicy {
+    /// Leave flushing to the OS until the track is finished.
+    Never,
+    /// Sync after every page.
+    EveryPage,
+    /// Sync after this many pages.
+    EveryPages(u32),
+    /// Sync once this long has passed since the last sync.
+    Interval(Duration),
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        SyncPolicy::Interval(Duration::from_secs(10))
+    }
+}
+
+/// Parses `never`, `page`, `pages:<n>` or `seconds:<n>`.
+impl FromStr for SyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "never" => Ok(SyncPolicy::Never),
+            None if s == "page" => Ok(SyncPolicy::EveryPage),
+            Some(("pages", n)) => match n.parse::<u32>() {
+                Ok(0) | Err(_) => Err(format!("Invalid page count in sync policy: {n}")),
+                Ok(n) => Ok(SyncPolicy::EveryPages(n)),
+            },
+            Some(("seconds", n)) => match n.parse::<f64>() {
+                Ok(n) if n > 0.0 && n.is_finite() => Ok(SyncPolicy::Interval(Duration::from_secs_f64(n))),
+                _ => Err(format!("Invalid interval in sync policy: {n}")),
+            },
+            _ => Err(format!("Unknown sync policy: {s} (expected never, page, pages:<n> or seconds:<n>)")),
+        }
+    }
+}
+
+impl SyncPolicy {
+    /// Whether a track should be synced, given the pages written and time passed since it was last synced.
+    pub fn is_due(&self, pages: u32, last_sync: Instant) -> bool {
+        match self {
+            SyncPolicy::Never => false,
+            SyncPolicy::EveryPage => true,
+            SyncPolicy::EveryPages(n) => pages >= *n,
+            SyncPolicy::Interval(interval) => last_sync.elapsed() >= *interval,
+        }
+    }
+}
+
+/// A snapshot of `SyncMetrics`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SyncStats {
+    /// Syncs attempted.
+    pub syncs: u64,
+    /// Syncs that returned an error.
+    pub failures: u64,
+    /// Time spent in all syncs.
+    pub total: Duration,
+    /// The longest single sync.
+    pub max: Duration,
+}
+
+impl SyncStats {
+    /// Average time per sync, or zero if there were none.
+    pub fn mean(&self) -> Duration {
+        self.total.checked_div(self.syncs as u32).unwrap_or_default()
+    }
+}
+
+/// Running totals of how long `fsync` calls take, shared by every writer.
+#[derive(Debug, Default)]
+pub struct SyncMetrics {
+    syncs: AtomicU64,
+    failures: AtomicU64,
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl SyncMetrics {
+    /// Counts a sync that took `took`.
+    pub fn record(&self, took: Duration, success: bool) {
+        let micros = took.as_micros() as u64;
+
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// A snapshot of the totals so far.
+    pub fn stats(&self) -> SyncStats {
+        SyncStats {
+            syncs: self.syncs.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            total: Duration::from_micros(self.total_micros.load(Ordering::Relaxed)),
+            max: Duration::from_micros(self.max_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Syncs a directory so that the files created in it survive a power loss.
+pub fn sync_dir(dir: &Path, metrics: &SyncMetrics) -> Result<(), RecorderError> {
+    let start = Instant::now();
+    let res = std::fs::File::open(dir).and_then(|x| x.sync_all());
+    metrics.record(start.elapsed(), res.is_ok());
+
+    res.map_err(|e| RecorderError::io("sync directory", dir, e))
+}
\ No newline at end of file
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use age::x25519::Recipient;
+use age::{Decryptor, Encryptor, IdentityFile};
+use serenity::all::GuildId;
+use crate::recorder::error::{EncryptionError, RecorderError};
+
+/// Extension appended to files once they have been encrypted.
+pub const ENCRYPTED_EXT: &str = "age";
+
+/// Per-guild age (X25519) recipients that finished recordings are encrypted to.
+#[derive(Clone, Debug, Default)]
+pub struct EncryptionConfig {
+    keys: HashMap<GuildId, Vec<Recipient>>,
+    /// Remove the plaintext tracks and zip once the encrypted copy has been written.
+    pub delete_plaintext: bool,
+}
+
+impl EncryptionConfig {
+    /// Reads a keys file.
+    ///
+    /// Each line holds a guild ID and an `age1...` public key separated by whitespace; a guild may be listed
+    /// more than once to give it several key holders. Blank lines and lines starting with `#` are ignored.
+    pub fn load(keys_path: &Path, delete_plaintext: bool) -> Result<Self, RecorderError> {
+        let contents = std::fs::read_to_string(keys_path).map_err(|e| RecorderError::io("read", keys_path, e))?;
+
+        let mut keys: HashMap<GuildId, Vec<Recipient>> = HashMap::new();
+        for (line_no, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |problem: String| EncryptionError::InvalidKey {
+                path: keys_path.to_path_buf(),
+                line: line_no + 1,
+                problem,
+            };
+
+            let (guild, key) = line.split_once(char::is_whitespace)
+                .ok_or_else(|| invalid("expected `<guild_id> <public key>`".to_string()))?;
+
+            let guild = guild.parse::<u64>()
+                .map_err(|e| invalid(format!("invalid guild ID: {e}")))?;
+            let key = key.trim().parse::<Recipient>()
+                .map_err(|e| invalid(format!("invalid age public key: {e}")))?;
+
+            keys.entry(GuildId::new(guild)).or_default().push(key);
+        }
+
+        Ok(Self {
+            keys,
+            delete_plaintext,
+        })
+    }
+
+    /// The recipients for a guild, or `None` if its recordings should be left unencrypted.
+    pub fn recipients(&self, guild_id: GuildId) -> Option<&[Recipient]> {
+        self.keys.get(&guild_id).map(|x| x.as_slice()).filter(|x| !x.is_empty())
+    }
+}
+
+/// Path of the encrypted copy of a file, e.g. `session.zip.age`.
+pub fn encrypted_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(ENCRYPTED_EXT);
+    PathBuf::from(name)
+}
+
+/// Encrypts `path` to every recipient, writing the result alongside it.
+pub fn encrypt_file(path: &Path, recipients: &[Recipient]) -> Result<PathBuf, RecorderError> {
+    let out_path = encrypted_path(path);
+
+    let encryptor = Encryptor::with_recipients(recipients.iter().map(|x| x as &dyn age::Recipient))
+        .map_err(EncryptionError::Encrypt)?;
+
+    let mut input = File::open(path).map_err(|e| RecorderError::io("open", path, e))?;
+    let output = File::create(&out_path).map_err(|e| RecorderError::io("create", &out_path, e))?;
+
+    let mut writer = encryptor.wrap_output(BufWriter::new(output)).map_err(|e| RecorderError::io("write to", &out_path, e))?;
+    std::io::copy(&mut input, &mut writer).map_err(|e| RecorderError::io("encrypt", path, e))?;
+
+    let mut output = writer.finish().map_err(|e| RecorderError::io("finish", &out_path, e))?;
+    output.flush().map_err(|e| RecorderError::io("flush", &out_path, e))?;
+
+    Ok(out_path)
+}
+
+/// Decrypts `input` with the identities in `identity_file` (as written by `age-keygen`).
+/// If `output` is not given, the `.age` extension is stripped from the input path.
+pub fn decrypt_file(identity_file: &Path, input: &Path, output: Option<&Path>) -> Result<PathBuf, RecorderError> {
+    let out_path = match output {
+        Some(x) => x.to_path_buf(),
+        None => {
+            if input.extension().is_none_or(|x| x != ENCRYPTED_EXT) {
+                return Err(EncryptionError::NoOutputPath(input.to_path_buf()).into());
+            }
+            input.with_extension("")
+        }
+    };
+
+    let identities = IdentityFile::from_file(identity_file.to_string_lossy().to_string())
+        .map_err(|e| RecorderError::io("read identity file", identity_file, e))?
+        .into_identities()
+        .map_err(|source| EncryptionError::Decrypt { path: identity_file.to_path_buf(), source })?;
+
+    let input_file = File::open(input).map_err(|e| RecorderError::io("open", input, e))?;
+    let decryptor = Decryptor::new_buffered(BufReader::new(input_file))
+        .map_err(|source| EncryptionError::Decrypt { path: input.to_path_buf(), source })?;
+    let mut reader = decryptor.decrypt(identities.iter().map(|x| x.as_ref()))
+        .map_err(|source| EncryptionError::Decrypt { path: input.to_path_buf(), source })?;
+
+    let output_file = File::create(&out_path).map_err(|e| RecorderError::io("create", &out_path, e))?;
+    let mut writer = BufWriter::new(output_file);
+    std::io::copy(&mut reader, &mut writer).map_err(|e| RecorderError::io("decrypt", input, e))?;
+    writer.flush().map_err(|e| RecorderError::io("flush", &out_path, e))?;
+
+    Ok(out_path)
+}
@@ -0,0 +1,326 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+use async_zip::error::ZipError;
+use songbird::error::JoinError;
+use tokio::task::JoinError as TaskJoinError;
+use crate::recorder::encryption::ENCRYPTED_EXT;
+
+/// Anything that can go wrong while recording, from the disk up to Discord.
+#[derive(Debug)]
+pub enum RecorderError {
+    /// Reading or writing a file failed, e.g. because the disk is full.
+    Io {
+        /// What was being done, e.g. `"write to"`.
+        action: &'static str,
+        /// The file or directory involved.
+        path: PathBuf,
+        /// The underlying error.
+        source: io::Error,
+    },
+    /// An Opus packet is malformed, or an Ogg page couldn't be built from it.
+    Mux(MuxError),
+    /// A packet dump couldn't be replayed because it isn't one, or is from an incompatible version.
+    Dump {
+        /// The dump file.
+        path: PathBuf,
+        /// What is wrong with it.
+        problem: String,
+    },
+    /// Joining or leaving a voice channel failed.
+    Discord {
+        /// What was being done, e.g. `"join"`.
+        action: &'static str,
+        /// The error from Songbird.
+        source: Box<JoinError>,
+    },
+    /// The request doesn't make sense for the current state of the recording.
+    State(StateError),
+    /// A zip archive couldn't be written.
+    Zip {
+        /// The archive being written.
+        path: PathBuf,
+        /// The underlying error.
+        source: ZipError,
+    },
+    /// A checksum file has a line that isn't a digest and the name of a file in the recording.
+    Checksum {
+        /// The checksum file.
+        path: PathBuf,
+        /// The offending line.
+        line: String,
+    },
+    /// Encrypting or decrypting a file failed.
+    Encryption(EncryptionError),
+    /// A storage backend failed.
+    Storage(StorageError),
+    /// A background task panicked or was cancelled.
+    Task {
+        /// Name of the task.
+        name: &'static str,
+        /// Why it ended.
+        source: TaskJoinError,
+    },
+}
+
+/// A request that doesn't fit the current state of a guild's recording.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    /// The guild has no recording running.
+    NotRecording,
+    /// A clip was requested that is longer than the history kept.
+    ClipTooLong {
+        /// The longest clip that can be made, in minutes.
+        max_minutes: u64,
+    },
+    /// No audio has been received yet, so there is nothing to clip.
+    NothingToClip,
+    /// The ID isn't the name of a recording directory.
+    InvalidRecordingId(String),
+    /// There is no recording with this ID in the guild.
+    RecordingNotFound(String),
+}
+
+/// Something wrong with an Opus packet, or with building the Ogg stream it goes into.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MuxError {
+    /// The segments of a page don't fit on a single Ogg page.
+    PageTooLarge,
+    /// A track has no segment to write to.
+    NoSegments,
+    /// A packet is empty, so it doesn't even have a TOC byte.
+    EmptyPacket,
+    /// A packet ends before the frame count, frame length or padding its TOC byte calls for.
+    Truncated(&'static str),
+    /// A packet's frames don't fit the layout its TOC byte gives, e.g. a code 1 packet of odd length.
+    InvalidToc(String),
+    /// Frame or padding lengths add up to more than the packet holds.
+    FrameLength {
+        /// Bytes the lengths add up to.
+        declared: usize,
+        /// Bytes left in the packet.
+        available: usize,
+    },
+    /// A frame is larger than the 1275 bytes Opus allows.
+    FrameTooLarge(usize),
+    /// A track ends before its ID or comment header.
+    MissingHeader(&'static str),
+    /// The Opus decoder or encoder failed while repairing a track.
+    Codec {
+        /// Position in the track, in samples.
+        position: u64,
+        /// What went wrong.
+        problem: String,
+    },
+}
+
+/// Something wrong with the keys recordings are encrypted to, or with encrypting or decrypting a file.
+#[derive(Debug)]
+pub enum EncryptionError {
+    /// A line of the keys file isn't a guild ID followed by an age public key.
+    InvalidKey {
+        /// The keys file.
+        path: PathBuf,
+        /// Line number, starting at 1.
+        line: usize,
+        /// What is wrong with it.
+        problem: String,
+    },
+    /// age couldn't encrypt the file.
+    Encrypt(age::EncryptError),
+    /// The file isn't an age file, or none of the identities can decrypt it.
+    Decrypt {
+        /// The file being decrypted.
+        path: PathBuf,
+        /// The error from age.
+        source: age::DecryptError,
+    },
+    /// No output path was given for a file that doesn't end in `.age`.
+    NoOutputPath(PathBuf),
+}
+
+/// Something that went wrong handing a finished recording to its storage backend.
+#[derive(Debug)]
+pub enum StorageError {
+    /// The backend's settings are incomplete.
+    Config(String),
+    /// The S3 bucket rejected a request.
+    S3 {
+        /// What was being done, e.g. `"upload"`.
+        action: String,
+        /// The error from the object store.
+        source: object_store::Error,
+    },
+    /// The SFTP server couldn't be reached.
+    Connect {
+        /// The address being connected to.
+        addr: String,
+        /// The underlying error.
+        source: io::Error,
+    },
+    /// The SFTP server's host key couldn't be verified against the known hosts file.
+    HostKey {
+        /// The server's host name.
+        host: String,
+        /// Why the key couldn't be verified.
+        problem: &'static str,
+    },
+    /// The SSH session or SFTP transfer failed.
+    Ssh {
+        /// What was being done, e.g. `"authenticate"`.
+        action: String,
+        /// The error from libssh2.
+        source: ssh2::Error,
+    },
+}
+
+impl RecorderError {
+    /// An `Io` error for `action` on `path`.
+    pub fn io(action: &'static str, path: impl Into<PathBuf>, source: io::Error) -> Self {
+        RecorderError::Io {
+            action,
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+impl Display for RecorderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecorderError::Io { action, path, source } => {
+                write!(f, "Failed to {action} {}: {source}", path.display())?;
+                if source.kind() == io::ErrorKind::StorageFull {
+                    write!(f, " (the disk is full)")?;
+                }
+                Ok(())
+            }
+            RecorderError::Mux(e) => write!(f, "Failed to write Opus stream: {e}"),
+            RecorderError::Dump { path, problem } => write!(f, "Failed to replay {}: {problem}", path.display()),
+            RecorderError::Discord { action, source } => write!(f, "Failed to {action}: {source}"),
+            RecorderError::State(e) => write!(f, "{e}"),
+            RecorderError::Zip { path, source } => write!(f, "Failed to write zip archive {}: {source}", path.display()),
+            RecorderError::Checksum { path, line } => write!(f, "Invalid line in {}: {line}", path.display()),
+            RecorderError::Encryption(e) => write!(f, "{e}"),
+            RecorderError::Storage(e) => write!(f, "{e}"),
+            RecorderError::Task { name, source } => write!(f, "{name} task failed: {source}"),
+        }
+    }
+}
+
+impl Error for RecorderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RecorderError::Io { source, .. } => Some(source),
+            RecorderError::Discord { source, .. } => Some(source.as_ref()),
+            RecorderError::State(e) => Some(e),
+            RecorderError::Zip { source, .. } => Some(source),
+            RecorderError::Encryption(e) => Some(e),
+            RecorderError::Storage(e) => Some(e),
+            RecorderError::Task { source, .. } => Some(source),
+            RecorderError::Mux(e) => Some(e),
+            RecorderError::Dump { .. } | RecorderError::Checksum { .. } => None,
+        }
+    }
+}
+
+impl From<MuxError> for RecorderError {
+    fn from(value: MuxError) -> Self {
+        RecorderError::Mux(value)
+    }
+}
+
+impl From<StateError> for RecorderError {
+    fn from(value: StateError) -> Self {
+        RecorderError::State(value)
+    }
+}
+
+impl From<EncryptionError> for RecorderError {
+    fn from(value: EncryptionError) -> Self {
+        RecorderError::Encryption(value)
+    }
+}
+
+impl From<StorageError> for RecorderError {
+    fn from(value: StorageError) -> Self {
+        RecorderError::Storage(value)
+    }
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::NotRecording => write!(f, "Not currently recording a call!"),
+            StateError::ClipTooLong { max_minutes } => write!(f, "Only the last {max_minutes} minute(s) are kept for clips"),
+            StateError::NothingToClip => write!(f, "Nobody has spoken in that time!"),
+            StateError::InvalidRecordingId(id) => write!(f, "`{id}` is not a valid recording ID"),
+            StateError::RecordingNotFound(id) => write!(f, "No recording `{id}` found"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+impl Display for MuxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MuxError::PageTooLarge => write!(f, "Packets don't fit on an Ogg page"),
+            MuxError::NoSegments => write!(f, "Track has no segments"),
+            MuxError::EmptyPacket => write!(f, "Empty packet"),
+            MuxError::Truncated(what) => write!(f, "Packet is truncated: {what} is missing"),
+            MuxError::InvalidToc(problem) => write!(f, "{problem}"),
+            MuxError::FrameLength { declared, available } => write!(f, "Frame lengths ({declared} bytes) exceed payload ({available} bytes)"),
+            MuxError::FrameTooLarge(len) => write!(f, "Frame too large: {len} bytes"),
+            MuxError::MissingHeader(header) => write!(f, "Track is missing its {header} header"),
+            MuxError::Codec { position, problem } => write!(f, "{problem} at sample {position}"),
+        }
+    }
+}
+
+impl Error for MuxError {}
+
+impl Display for EncryptionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncryptionError::InvalidKey { path, line, problem } => write!(f, "{}:{line}: {problem}", path.display()),
+            EncryptionError::Encrypt(e) => write!(f, "Failed to set up encryption: {e}"),
+            EncryptionError::Decrypt { path, source } => write!(f, "Failed to decrypt {}: {source}", path.display()),
+            EncryptionError::NoOutputPath(path) => write!(f, "{} does not end in .{ENCRYPTED_EXT}, please give an output path", path.display()),
+        }
+    }
+}
+
+impl Error for EncryptionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EncryptionError::Encrypt(e) => Some(e),
+            EncryptionError::Decrypt { source, .. } => Some(source),
+            EncryptionError::InvalidKey { .. } | EncryptionError::NoOutputPath(_) => None,
+        }
+    }
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::Config(e) => write!(f, "{e}"),
+            StorageError::S3 { action, source } => write!(f, "Failed to {action}: {source}"),
+            StorageError::Connect { addr, source } => write!(f, "Failed to connect to {addr}: {source}"),
+            StorageError::HostKey { host, problem } => write!(f, "Host key for {host} {problem}"),
+            StorageError::Ssh { action, source } => write!(f, "Failed to {action}: {source}"),
+        }
+    }
+}
+
+impl Error for StorageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StorageError::S3 { source, .. } => Some(source),
+            StorageError::Connect { source, .. } => Some(source),
+            StorageError::Ssh { source, .. } => Some(source),
+            StorageError::Config(_) | StorageError::HostKey { .. } => None,
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+
+/// Counters kept by a `JitterBuffer` over its lifetime.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JitterStats {
+    pub received: u64,
+    /// Packets that arrived after a packet with a higher sequence number, but in time to be put back in place.
+    pub reordered: u64,
+    pub duplicates: u64,
+    /// Packets that arrived after their slot had already been released.
+    pub late: u64,
+    /// Sequence numbers that were skipped because the packet never arrived in time.
+    pub lost: u64,
+}
+
+/// A small per-SSRC reorder buffer keyed on RTP sequence number.
+///
+/// Packets are held until `depth` newer packets have arrived, then released in sequence order.
+#[derive(Debug)]
+pub struct JitterBuffer<T> {
+    depth: usize,
+    packets: BTreeMap<u64, T>,
+    /// Highest extended sequence number seen so far, used to unwrap the 16-bit RTP sequence.
+    highest: Option<u64>,
+    /// Extended sequence number of the next packet to be released.
+    next: Option<u64>,
+    /// Number of ticks since the last packet arrived.
+    idle_ticks: usize,
+    stats: JitterStats,
+}
+
+impl<T> JitterBuffer<T> {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            packets: BTreeMap::new(),
+            highest: None,
+            next: None,
+            idle_ticks: 0,
+            stats: JitterStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> JitterStats {
+        self.stats
+    }
+
+    /// Adds a packet to the buffer, returning any packets that are now ready to be written, in order.
+    pub fn push(&mut self, sequence: u16, packet: T) -> Vec<T> {
+        self.stats.received += 1;
+        self.idle_ticks = 0;
+
+        let extended = match self.highest {
+            None => sequence as u64 + (1 << 16),
+            Some(highest) => {
+                let delta = sequence.wrapping_sub(highest as u16) as i16 as i64;
+                (highest as i64 + delta).max(0) as u64
+            }
+        };
+
+        if self.next.is_some_and(|next| extended < next) {
+            self.stats.late += 1;
+            return Vec::new();
+        }
+
+        if self.packets.contains_key(&extended) {
+            self.stats.duplicates += 1;
+            return Vec::new();
+        }
+
+        if self.highest.is_some_and(|highest| extended < highest) {
+            self.stats.reordered += 1;
+        }
+
+        self.highest = Some(self.highest.map_or(extended, |highest| highest.max(extended)));
+        self.packets.insert(extended, packet);
+
+        let mut ready = Vec::new();
+        while self.packets.len() > self.depth {
+            ready.extend(self.pop());
+        }
+
+        ready
+    }
+
+    /// Called once per voice tick in which no packet arrived for this SSRC.
+    /// Once the speaker has been quiet for `depth` ticks, everything left in the buffer is released.
+    pub fn tick(&mut self) -> Vec<T> {
+        self.idle_ticks += 1;
+
+        if self.idle_ticks >= self.depth {
+            self.flush()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Releases every buffered packet, in order.
+    pub fn flush(&mut self) -> Vec<T> {
+        let mut ready = Vec::new();
+        while !self.packets.is_empty() {
+            ready.extend(self.pop());
+        }
+
+        ready
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let (sequence, packet) = self.packets.pop_first()?;
+
+        if let Some(next) = self.next {
+            self.stats.lost += sequence.saturating_sub(next);
+        }
+        self.next = Some(sequence + 1);
+
+        Some(packet)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pushes each sequence number as its own packet, then flushes, returning everything released in order.
+    fn run(buffer: &mut JitterBuffer<u16>, sequences: &[u16]) -> Vec<u16> {
+        let mut released = Vec::new();
+        for &sequence in sequences {
+            released.extend(buffer.push(sequence, sequence));
+        }
+        released.extend(buffer.flush());
+        released
+    }
+
+    #[test]
+    fn reorders_within_depth() {
+        let mut buffer = JitterBuffer::new(3);
+        assert_eq!(run(&mut buffer, &[1, 3, 2, 5, 4, 6]), [1, 2, 3, 4, 5, 6]);
+
+        let stats = buffer.stats();
+        assert_eq!(stats.received, 6);
+        assert_eq!(stats.reordered, 2);
+        assert_eq!(stats.lost, 0);
+    }
+
+    #[test]
+    fn holds_packets_until_depth_newer_ones_arrive() {
+        let mut buffer = JitterBuffer::new(2);
+        assert!(buffer.push(1, 1).is_empty());
+        assert!(buffer.push(2, 2).is_empty());
+        assert_eq!(buffer.push(3, 3), [1]);
+
+        assert!(buffer.tick().is_empty());
+        assert_eq!(buffer.tick(), [2, 3]);
+    }
+
+    #[test]
+    fn drops_duplicates() {
+        let mut buffer = JitterBuffer::new(3);
+        assert_eq!(run(&mut buffer, &[1, 2, 2, 3, 1]), [1, 2, 3]);
+        assert_eq!(buffer.stats().duplicates, 2);
+        assert_eq!(buffer.stats().late, 0);
+    }
+
+    #[test]
+    fn drops_packets_arriving_after_release() {
+        let mut buffer = JitterBuffer::new(1);
+        assert_eq!(run(&mut buffer, &[1, 2, 4, 5, 3, 2, 6]), [1, 2, 4, 5, 6]);
+
+        let stats = buffer.stats();
+        assert_eq!(stats.late, 2);
+        assert_eq!(stats.reordered, 0);
+        // 3 was skipped when 4 was released, and arrived too late to fill the gap.
+        assert_eq!(stats.lost, 1);
+    }
+
+    #[test]
+    fn counts_lost_packets() {
+        let mut buffer = JitterBuffer::new(2);
+        assert_eq!(run(&mut buffer, &[10, 11, 14, 15, 20]), [10, 11, 14, 15, 20]);
+        assert_eq!(buffer.stats().lost, 2 + 4);
+    }
+
+    #[test]
+    fn unwraps_sequence_numbers() {
+        let mut buffer = JitterBuffer::new(2);
+        assert_eq!(run(&mut buffer, &[65534, 65535, 1, 0, 2]), [65534, 65535, 0, 1, 2]);
+        assert_eq!(buffer.stats().lost, 0);
+        assert_eq!(buffer.stats().reordered, 1);
+    }
+
+    #[test]
+    fn accepts_packets_from_before_the_first_one() {
+        // The first sequence number is unwrapped one cycle in, so a reordered earlier packet doesn't go negative.
+        let mut buffer = JitterBuffer::new(3);
+        assert_eq!(run(&mut buffer, &[0, 65535, 1]), [65535, 0, 1]);
+        assert_eq!(buffer.stats().reordered, 1);
+        assert_eq!(buffer.stats().lost, 0);
+    }
+}
\ No newline at end of file
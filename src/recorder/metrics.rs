@@ -0,0 +1,245 @@
+use crate::recorder::durability::SyncMetrics;
+use dashmap::DashMap;
+use serenity::all::{GuildId, UserId};
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Histogram buckets for durations, in seconds.
+pub const DURATION_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+/// Histogram buckets for file sizes, in bytes.
+pub const SIZE_BUCKETS: &[f64] = &[1e6, 1e7, 5e7, 1e8, 2.5e8, 5e8, 1e9, 2.5e9, 5e9];
+
+/// Writes the `# HELP` and `# TYPE` lines that start a metric in the Prometheus text format.
+pub fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    _ = writeln!(out, "# HELP {name} {help}");
+    _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Writes a single sample in the Prometheus text format.
+pub fn write_sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl Display) {
+    out.push_str(name);
+
+    if !labels.is_empty() {
+        out.push('{');
+        for (i, (label, value)) in labels.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            _ = write!(out, "{label}=\"{value}\"");
+        }
+        out.push('}');
+    }
+
+    _ = writeln!(out, " {value}");
+}
+
+/// A Prometheus histogram with fixed buckets.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    state: Mutex<HistogramState>,
+}
+
+#[derive(Debug)]
+struct HistogramState {
+    /// Observations per bucket, not cumulative. The last one is `+Inf`.
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    /// A histogram with a bucket for each of `bounds`, plus `+Inf`.
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            state: Mutex::new(HistogramState {
+                counts: vec![0; bounds.len() + 1],
+                sum: 0.0,
+            }),
+        }
+    }
+
+    /// Counts `value` in its bucket.
+    pub fn observe(&self, value: f64) {
+        let bucket = self.bounds.iter().position(|x| value <= *x).unwrap_or(self.bounds.len());
+
+        let mut state = self.state.lock().unwrap();
+        state.counts[bucket] += 1;
+        state.sum += value;
+    }
+
+    /// Writes the `_bucket`, `_sum` and `_count` samples of the histogram.
+    pub fn write(&self, out: &mut String, name: &str, labels: &[(&str, &str)]) {
+        let state = self.state.lock().unwrap();
+
+        let bucket_name = format!("{name}_bucket");
+        let mut cumulative = 0;
+        for (i, count) in state.counts.iter().enumerate() {
+            cumulative += count;
+            let le = self.bounds.get(i).map(|x| x.to_string()).unwrap_or("+Inf".to_string());
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", &le));
+            write_sample(out, &bucket_name, &bucket_labels, cumulative);
+        }
+
+        write_sample(out, &format!("{name}_sum"), labels, state.sum);
+        write_sample(out, &format!("{name}_count"), labels, cumulative);
+    }
+}
+
+/// Packet counters of one guild, kept across recordings.
+#[derive(Debug, Default)]
+struct GuildCounters {
+    voice_packets: AtomicU64,
+    silence_packets: AtomicU64,
+    lost_packets: AtomicU64,
+    out_of_order_packets: AtomicU64,
+    resyncs: AtomicU64,
+    dropped_ticks: AtomicU64,
+}
+
+/// What has been written to one track, kept until its recording finishes.
+#[derive(Debug, Default)]
+struct StreamCounters {
+    bytes: AtomicU64,
+    pages: AtomicU64,
+}
+
+/// Running totals of what the writers have done, shared by every writer and exposed on `/metrics`.
+#[derive(Debug)]
+pub struct RecorderMetrics {
+    /// How long syncing tracks to disk takes.
+    pub sync: SyncMetrics,
+    guilds: DashMap<GuildId, GuildCounters>,
+    streams: DashMap<(GuildId, UserId, u32), StreamCounters>,
+    zip_duration: Histogram,
+    zip_size: Histogram,
+}
+
+impl Default for RecorderMetrics {
+    fn default() -> Self {
+        Self {
+            sync: SyncMetrics::default(),
+            guilds: DashMap::new(),
+            streams: DashMap::new(),
+            zip_duration: Histogram::new(DURATION_BUCKETS),
+            zip_size: Histogram::new(SIZE_BUCKETS),
+        }
+    }
+}
+
+impl RecorderMetrics {
+    fn guild(&self, guild_id: GuildId, counter: impl Fn(&GuildCounters) -> &AtomicU64, count: u64) {
+        counter(&self.guilds.entry(guild_id).or_default()).fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// A packet of speech handed to a track.
+    pub fn voice_packet(&self, guild_id: GuildId) {
+        self.guild(guild_id, |x| &x.voice_packets, 1);
+    }
+
+    /// Silent frames written to fill gaps between packets.
+    pub fn silence_packets(&self, guild_id: GuildId, count: u64) {
+        self.guild(guild_id, |x| &x.silence_packets, count);
+    }
+
+    /// Packets missing from a gap in the RTP sequence numbers.
+    pub fn lost_packets(&self, guild_id: GuildId, count: u64) {
+        self.guild(guild_id, |x| &x.lost_packets, count);
+    }
+
+    /// A packet that arrived after a later one of the same track.
+    pub fn out_of_order_packet(&self, guild_id: GuildId) {
+        self.guild(guild_id, |x| &x.out_of_order_packets, 1);
+    }
+
+    /// A track whose RTP clock drifted too far from the wall clock and was re-anchored.
+    pub fn resync(&self, guild_id: GuildId) {
+        self.guild(guild_id, |x| &x.resyncs, 1);
+    }
+
+    /// An update dropped because the call's writer had fallen behind.
+    pub fn dropped_tick(&self, guild_id: GuildId) {
+        self.guild(guild_id, |x| &x.dropped_ticks, 1);
+    }
+
+    /// An Ogg page written to a track.
+    pub fn page_written(&self, guild_id: GuildId, user_id: UserId, ssrc: u32, bytes: usize) {
+        let stream = self.streams.entry((guild_id, user_id, ssrc)).or_default();
+        stream.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        stream.pages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Forgets the tracks of a finished recording.
+    pub fn remove_streams(&self, guild_id: GuildId) {
+        self.streams.retain(|(guild, _, _), _| *guild != guild_id);
+    }
+
+    /// A finished zip of a recording.
+    pub fn zip_finished(&self, took: Duration, bytes: u64) {
+        self.zip_duration.observe(took.as_secs_f64());
+        self.zip_size.observe(bytes as f64);
+    }
+
+    /// Writes every counter in the Prometheus text format.
+    pub fn write(&self, out: &mut String) {
+        let guilds = self.guilds.iter()
+            .map(|x| {
+                let counters = [&x.voice_packets, &x.silence_packets, &x.lost_packets, &x.out_of_order_packets, &x.resyncs, &x.dropped_ticks];
+                (x.key().to_string(), counters.map(|x| x.load(Ordering::Relaxed)))
+            })
+            .collect::<Vec<_>>();
+
+        let guild_counters = [
+            ("disrecord_voice_packets_total", "Packets of speech written to tracks."),
+            ("disrecord_silence_packets_total", "Silent frames written to fill gaps in tracks."),
+            ("disrecord_lost_packets_total", "Packets missing from gaps in the RTP sequence."),
+            ("disrecord_out_of_order_packets_total", "Packets dropped for arriving after a later one was written."),
+            ("disrecord_resyncs_total", "Tracks re-anchored because their RTP clock drifted from the wall clock."),
+            ("disrecord_dropped_voice_ticks_total", "Voice ticks dropped because a call's writer had fallen behind."),
+        ];
+        for (i, (name, help)) in guild_counters.into_iter().enumerate() {
+            write_header(out, name, "counter", help);
+            for (guild, counters) in &guilds {
+                write_sample(out, name, &[("guild", guild)], counters[i]);
+            }
+        }
+
+        let streams = self.streams.iter()
+            .map(|x| {
+                let (guild, user, ssrc) = x.key();
+                let labels = [guild.to_string(), user.to_string(), ssrc.to_string()];
+                (labels, [x.bytes.load(Ordering::Relaxed), x.pages.load(Ordering::Relaxed)])
+            })
+            .collect::<Vec<_>>();
+
+        let stream_counters = [
+            ("disrecord_stream_bytes_written_total", "Bytes written to each track of the running recordings."),
+            ("disrecord_stream_ogg_pages_written_total", "Ogg pages written to each track of the running recordings."),
+        ];
+        for (i, (name, help)) in stream_counters.into_iter().enumerate() {
+            write_header(out, name, "counter", help);
+            for ([guild, user, ssrc], counters) in &streams {
+                write_sample(out, name, &[("guild", guild), ("user", user), ("ssrc", ssrc)], counters[i]);
+            }
+        }
+
+        write_header(out, "disrecord_zip_duration_seconds", "histogram", "Time taken to zip a finished recording.");
+        self.zip_duration.write(out, "disrecord_zip_duration_seconds", &[]);
+
+        write_header(out, "disrecord_zip_size_bytes", "histogram", "Size of the zip of a finished recording.");
+        self.zip_size.write(out, "disrecord_zip_size_bytes", &[]);
+
+        let sync = self.sync.stats();
+        write_header(out, "disrecord_fsyncs_total", "counter", "Calls to fsync while writing tracks.");
+        write_sample(out, "disrecord_fsyncs_total", &[], sync.syncs);
+        write_header(out, "disrecord_fsync_failures_total", "counter", "Calls to fsync that failed.");
+        write_sample(out, "disrecord_fsync_failures_total", &[], sync.failures);
+        write_header(out, "disrecord_fsync_seconds_total", "counter", "Time spent in fsync.");
+        write_sample(out, "disrecord_fsync_seconds_total", &[], sync.total.as_secs_f64());
+    }
+}
\ No newline at end of file
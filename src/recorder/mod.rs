@@ -1,31 +1,164 @@
 use std::collections::HashSet;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use chrono::{DateTime, Utc};
-use serenity::all::{GuildId, UserId};
+use serenity::all::{ChannelId, GuildId, UserId};
 use tokio::sync::oneshot::Receiver;
+use tokio::sync::watch;
+use crate::recorder::durability::SyncPolicy;
+use crate::recorder::encryption::EncryptionConfig;
+use crate::recorder::error::RecorderError;
+use crate::recorder::storage::{StorageBackend, StorageLocation};
+use crate::recorder::track_name::TrackNameTemplate;
+use crate::recorder::writer::clock::Clock;
+use crate::recorder::writer::muxer::opus_toc::GRANULE_SAMPLE_RATE;
 
+mod jitter_buffer;
 mod voice_receiver;
-mod writer;
+/// Writing calls to disk.
+pub mod writer;
+/// Checksum files of finished recordings.
+pub mod checksums;
+/// When tracks are synced to disk.
+pub mod durability;
+/// Encrypting recordings with age.
+pub mod encryption;
+/// Errors returned while recording.
+pub mod error;
+/// Counters exposed on `/metrics`.
+pub mod metrics;
+/// The recorder that joins calls and hands them to the writer.
 pub mod recorder;
+/// Where finished recordings are stored.
+pub mod storage;
+/// Naming track files.
+pub mod track_name;
 
+/// Describes a recording; written into its tracks and summaries.
 #[derive(Clone, Debug)]
 pub struct RecordingMetadata {
+    /// The recorded guild.
     pub guild_id: GuildId,
+    /// Name of the guild, if known.
+    pub guild_name: Option<String>,
+    /// The recorded voice channel.
+    pub channel_id: ChannelId,
+    /// Name of the channel, if known.
+    pub channel_name: Option<String>,
+    /// Random identifier for this recording, written into every track's tags.
+    pub session_id: String,
+    /// Directory the recording is written to.
     pub output_dir: PathBuf,
+    /// Name of `output_dir`, which is also the recording's ID.
     pub output_dir_name: String,
+    /// When the recording started.
     pub started: DateTime<Utc>,
+    /// Monotonic clock reading at the start of the recording; voice state events are placed relative to this.
+    pub epoch: Instant,
+    /// Position on the recording's timeline, which stream positions are measured against.
+    pub clock: Clock,
 }
 
+/// What happened during a finished recording, along with the zip being made of it.
 #[derive(Debug)]
 pub struct RecordingSummary {
+    /// Name of the recording's directory, used to refer to it in later commands.
+    pub id: String,
+    /// When the recording started.
     pub started: DateTime<Utc>,
+    /// When the recording finished.
     pub ended: DateTime<Utc>,
+    /// The voice channel that was recorded.
+    pub channel_id: ChannelId,
+    /// Users who spoke.
     pub known_users: HashSet<UserId>,
-    pub zip_rx: Receiver<Result<PathBuf, String>>,
+    /// Users who were in the channel at some point but never spoke.
+    pub silent_users: HashSet<UserId>,
+    /// Receives the stored recording once it has been zipped.
+    pub zip_rx: Receiver<Result<StoredRecording, RecorderError>>,
+    /// How far along the zip is.
+    pub zip_progress: watch::Receiver<ZipProgress>,
+    /// How many errors were hit while writing, e.g. because the disk filled up.
+    pub write_errors: u64,
+    /// The first error hit while writing, if any.
+    pub first_error: Option<RecorderError>,
 }
 
+/// A finished recording after it has been zipped and stored.
+#[derive(Clone, Debug)]
+pub struct StoredRecording {
+    /// Where the zip was stored.
+    pub location: StorageLocation,
+    /// Hex-encoded SHA-256 of the (unencrypted) zip.
+    pub sha256: String,
+}
+
+/// How far along the zip of a finished recording is.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ZipProgress {
+    /// Files written to the zip so far.
+    pub files_done: usize,
+    /// Files to be written.
+    pub files_total: usize,
+    /// Bytes written so far.
+    pub bytes_done: u64,
+    /// Bytes to be written.
+    pub bytes_total: u64,
+}
+
+/// Limits after which a track is continued in a new file. With neither set, each track is a single file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SegmentPolicy {
+    /// Longest a segment may run.
+    pub max_duration: Option<Duration>,
+    /// Largest a segment may grow, in bytes.
+    pub max_bytes: Option<u64>,
+}
+
+impl SegmentPolicy {
+    /// Whether tracks are split into segments at all.
+    pub fn is_enabled(&self) -> bool {
+        self.max_duration.is_some() || self.max_bytes.is_some()
+    }
+
+    /// Whether a segment holding `samples` of audio in `bytes` should be ended.
+    pub fn is_due(&self, samples: u64, bytes: u64) -> bool {
+        let too_long = self.max_duration.is_some_and(|x| samples as f64 >= x.as_secs_f64() * GRANULE_SAMPLE_RATE as f64);
+        let too_big = self.max_bytes.is_some_and(|x| bytes >= x);
+        too_long || too_big
+    }
+}
+
+/// Settings for recording, taken from the `[recording]` section of the config.
 #[derive(Clone, Debug)]
 pub struct RecorderConfig {
+    /// Directory recordings are written under, in a subdirectory per guild.
     pub base_dir: PathBuf,
+    /// `strftime` format of the directory name of each recording.
     pub subdir_fmt:  String,
+    /// How track files are named.
+    pub track_name: TrackNameTemplate,
+    /// Number of packets held per SSRC to put late packets back in order.
+    pub jitter_buffer_depth: usize,
+    /// How many voice ticks can be waiting for a call's writer task before new ones are dropped.
+    /// Other updates are never dropped.
+    pub queue_depth: usize,
+    /// Write a `.repaired.opus` copy of any track with lost packets, rebuilt with FEC/PLC, next to the raw one.
+    pub repair_lost_packets: bool,
+    /// How often tracks are flushed to disk while recording.
+    pub sync_policy: SyncPolicy,
+    /// When long tracks are split into separate files.
+    pub segment_policy: SegmentPolicy,
+    /// How much recent audio is kept in memory for `/clip`.
+    pub clip_history: Duration,
+    /// Where finished recordings are sent once zipped.
+    pub storage: Arc<dyn StorageBackend>,
+    /// Per-guild keys to encrypt finished recordings to; guilds without a key are left in plaintext.
+    pub encryption: Option<Arc<EncryptionConfig>>,
+    /// Save the packets of every call under `<guild>/captures/` so that it can be replayed later.
+    /// Captures are encrypted along with the recording for guilds with keys.
+    pub capture_packets: bool,
+    /// Log messages posted in the voice channel's chat alongside the audio.
+    pub capture_chat: bool,
 }
\ No newline at end of file
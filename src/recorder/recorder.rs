@@ -1,43 +1,55 @@
 use crate::recorder::voice_receiver::VoiceReceiver;
+use dashmap::DashMap;
 use crate::recorder::{RecorderConfig, RecordingSummary};
-use serenity::all::{ChannelId, Context, GuildId};
+use serenity::all::{ChannelId, Context, GuildId, Message, VoiceState};
+use serenity::prelude::TypeMapKey;
 use songbird::CoreEvent;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
 use tokio::time::sleep;
-use crate::recorder::writer::{VoiceUpdate, Writer};
+use crate::recorder::writer::{CallHealth, ChatMessage, VoiceUpdate, VoiceUpdateType, Writer};
+use crate::recorder::writer::timeline::{voice_state_events, VoiceEvent, VoiceEventKind};
+use crate::recorder::checksums::{verify_checksums, ChecksumResult};
+use crate::recorder::error::{RecorderError, StateError};
 
+/// Records voice channels, one call per guild. Shared through serenity's `TypeMap` so every handler can reach it.
 #[derive(Debug)]
 pub struct Recorder {
+    config: RecorderConfig,
     writer: Arc<Writer>,
-    voice_tx: mpsc::Sender<VoiceUpdate>
+    /// The receiver installed on each guild's call, kept so that it can be flushed when the recording finishes.
+    receivers: DashMap<GuildId, VoiceReceiver>,
 }
 
 impl Recorder {
+    /// Creates a recorder that writes recordings as set out in `config`.
     pub fn new(config: RecorderConfig) -> Self {
-        let (voice_tx, voice_rx) = mpsc::channel(1024);
-
-        let writer = Arc::new(Writer::new(config));
-        Writer::run(writer.clone(), voice_rx);
+        let writer = Arc::new(Writer::new(config.clone()));
 
         Self {
+            config,
             writer,
-            voice_tx,
+            receivers: DashMap::new(),
         }
     }
 
+    /// The recorder stored in the client's data, if there is one.
     pub async fn get(ctx: &Context) -> Option<Arc<Self>> {
         let data = ctx.data.read().await;
         data.get::<Self>().cloned()
     }
 
+    /// Whether the bot is in a call in the guild.
     pub async fn has_call(ctx: &Context, guild_id: GuildId) -> bool {
         let sbird = songbird::get(ctx).await.expect("Songbird doesn't exist!");
         sbird.get(guild_id).is_some()
     }
 
-    pub async fn join(&self, ctx: &Context, guild_id: GuildId, channel_id: ChannelId) -> Result<(), String> {
+    /// Joins the voice channel and starts a recording of it.
+    ///
+    /// Everyone already in the channel is added to the timeline as present.
+    pub async fn join(&self, ctx: &Context, guild_id: GuildId, channel_id: ChannelId) -> Result<(), RecorderError> {
         trace!("[{guild_id}] Joining: {channel_id}");
 
         let sbird = songbird::get(ctx).await.expect("Songbird doesn't exist!");
@@ -48,10 +60,11 @@ impl Recorder {
             let call_lock = sbird.get_or_insert(guild_id);
             let mut call = call_lock.lock().await;
 
-            let voice_receiver = VoiceReceiver::new(guild_id, ctx, self.voice_tx.clone()).await;
+            let voice_receiver = VoiceReceiver::new(guild_id, ctx, self.writer.clone(), self.config.jitter_buffer_depth).await;
 
             call.add_global_event(CoreEvent::VoiceTick.into(), voice_receiver.clone());
-            call.add_global_event(CoreEvent::SpeakingStateUpdate.into(), voice_receiver);
+            call.add_global_event(CoreEvent::SpeakingStateUpdate.into(), voice_receiver.clone());
+            self.receivers.insert(guild_id, voice_receiver);
         }
 
         // TODO: Check that channel is in the guild and that the bot has access to it before joining.
@@ -62,26 +75,52 @@ impl Recorder {
             // Although we failed to join, we need to clear out existing event handlers on the call.
             _ = sbird.remove(guild_id).await;
 
-            Err(format!("Failed to join voice channel: {e}"))
+            Err(RecorderError::Discord { action: "join voice channel", source: Box::new(e) })
         } else {
             info!("[{guild_id}] Joined channel {channel_id} and began recording!");
 
-            self.writer.start(guild_id);
+            let guild_name = guild_id.name(ctx);
+            let channel_name = match channel_id.name(ctx).await {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    warn!("[{guild_id}] Failed to get name of channel {channel_id}: {e:?}");
+                    None
+                }
+            };
+
+            self.writer.start(guild_id, guild_name, channel_id, channel_name);
+
+            // Note everyone already in the channel, since they won't send a voice state update.
+            let bot_id = ctx.cache.current_user().id;
+            let present = match guild_id.to_guild_cached(&ctx.cache) {
+                Some(guild) => guild.voice_states.values()
+                    .filter(|x| x.channel_id == Some(channel_id) && x.user_id != bot_id)
+                    .cloned()
+                    .collect::<Vec<_>>(),
+                None => {
+                    warn!("[{guild_id}] Guild is not cached, can't tell who is already in the channel");
+                    Vec::new()
+                }
+            };
+
+            for state in present {
+                self.send_voice_events(guild_id, None, &state, channel_id, true);
+            }
 
             Ok(())
         }
     }
 
-    pub async fn rejoin(&self, ctx: &Context, guild_id: GuildId, channel_id: ChannelId) -> Result<(), String> {
+    /// Moves an ongoing recording to another voice channel, e.g. after the bot was moved or disconnected.
+    ///
+    /// Fails with `StateError::NotRecording` if the guild has no call.
+    pub async fn rejoin(&self, ctx: &Context, guild_id: GuildId, channel_id: ChannelId) -> Result<(), RecorderError> {
         trace!("[{guild_id}] Re-joining: {channel_id}");
 
         let sbird = songbird::get(ctx).await.expect("Songbird doesn't exist!");
 
-        if sbird.get(guild_id).is_some() {
-            let old_channel_id = {
-                let call = sbird.get(guild_id).unwrap();
-                ChannelId::from(call.lock().await.current_channel().unwrap().0)
-            };
+        if let Some(call) = sbird.get(guild_id) {
+            let old_channel_id = call.lock().await.current_channel().map(|x| ChannelId::from(x.0)).ok_or(StateError::NotRecording)?;
 
             if old_channel_id == channel_id {
                 if let Err(e) = sbird.leave(guild_id).await {
@@ -90,7 +129,7 @@ impl Recorder {
                     // Although we failed to join, we need to clear out existing event handlers on the call.
                     _ = sbird.remove(guild_id).await;
 
-                    return Err(format!("Failed to leave voice channel: {e}"))
+                    return Err(RecorderError::Discord { action: "leave voice channel", source: Box::new(e) })
                 };
 
                 sleep(Duration::from_millis(500)).await;
@@ -104,46 +143,198 @@ impl Recorder {
                 // Although we failed to join, we need to clear out existing event handlers on the call.
                 _ = sbird.remove(guild_id).await;
 
-                Err(format!("Failed to join voice channel: {e}"))
+                Err(RecorderError::Discord { action: "join voice channel", source: Box::new(e) })
             } else {
                 info!("[{guild_id}] Joined channel {channel_id}");
                 Ok(())
             }
         } else {
             error!("[{guild_id}] Tried rejoin on {channel_id} but not currently in a call!");
-            Err("Not currently recording a call!".to_string())
+            Err(StateError::NotRecording.into())
         }
     }
 
-    pub async fn finish(&self, ctx: &Context, guild_id: GuildId) -> Result<RecordingSummary, String> {
+    /// Leaves the call and finishes its recording.
+    ///
+    /// The tracks are finalised before this returns; zipping and storing continue in the background and are
+    /// reported through the summary's `zip_rx`.
+    pub async fn finish(&self, ctx: &Context, guild_id: GuildId) -> Result<RecordingSummary, RecorderError> {
         let sbird = songbird::get(ctx).await.expect("Songbird doesn't exist!");
 
-        let has_call = sbird.get(guild_id).is_some();
-
-        if has_call {
-            let channel_id = {
-                let call = sbird.get(guild_id).unwrap();
-                ChannelId::from(call.lock().await.current_channel().unwrap().0)
-            };
+        if let Some(call) = sbird.get(guild_id) {
+            let channel_id = call.lock().await.current_channel().map(|x| ChannelId::from(x.0));
 
             if let Err(e) = sbird.remove(guild_id).await {
                 error!("[{guild_id}] Failed to leave channel: {e:?}");
-                Err(format!("Failed to leave channel: {e}"))
+                Err(RecorderError::Discord { action: "leave voice channel", source: Box::new(e) })
             } else {
-                info!("[{guild_id}] Left channel {channel_id} and finalized recording!");
-
-                match self.writer.finish(guild_id).await {
-                    None => {
-                        error!("[{guild_id}] Failed to finish recording!");
-                        Err("Failed to finish recording".to_string())
-                    },
-                    Some(summary) => Ok(summary)
+                match channel_id {
+                    Some(channel_id) => info!("[{guild_id}] Left channel {channel_id} and finalized recording!"),
+                    None => info!("[{guild_id}] Left call and finalized recording!"),
+                }
+
+                // No more packets can arrive, so whatever the jitter buffers still hold is the end of the audio.
+                if let Some((_, voice_receiver)) = self.receivers.remove(&guild_id) {
+                    voice_receiver.flush();
                 }
+
+                self.writer.finish(guild_id).await.inspect_err(|e| {
+                    error!("[{guild_id}] Failed to finish recording: {e}");
+                })
             }
         } else {
-            Err("Not in a voice channel!".to_string())
+            Err(StateError::NotRecording.into())
+        }
+    }
+
+    /// Settings the recorder was created with.
+    pub fn config(&self) -> &RecorderConfig {
+        &self.config
+    }
+
+    /// Guilds with a recording running.
+    pub fn active_guilds(&self) -> Vec<GuildId> {
+        self.writer.guilds()
+    }
+
+    /// The state of every running call's writer task.
+    pub fn health(&self) -> Vec<CallHealth> {
+        self.writer.health()
+    }
+
+    /// Checks that new recordings can be written to `base_dir`.
+    pub async fn check_base_dir(&self) -> Result<(), RecorderError> {
+        self.writer.check_base_dir().await
+    }
+
+    /// Writes the recorder's metrics in the Prometheus text format.
+    pub fn write_metrics(&self, out: &mut String) {
+        self.writer.write_metrics(out);
+    }
+
+    /// Adds any changes between two voice states to the timeline of the recording in that guild.
+    pub async fn log_voice_state(&self, ctx: &Context, old: Option<&VoiceState>, new: &VoiceState) {
+        let guild_id = match new.guild_id {
+            Some(x) => x,
+            None => return,
+        };
+
+        if new.user_id == ctx.cache.current_user().id {
+            return;
+        }
+
+        let sbird = songbird::get(ctx).await.expect("Songbird doesn't exist!");
+
+        let channel_id = match sbird.get(guild_id) {
+            Some(call) => call.lock().await.current_channel().map(|x| ChannelId::from(x.0)),
+            None => return,
+        };
+
+        if let Some(channel_id) = channel_id {
+            self.send_voice_events(guild_id, old, new, channel_id, false);
         }
     }
+
+    fn send_voice_events(&self, guild_id: GuildId, old: Option<&VoiceState>, new: &VoiceState, channel_id: ChannelId, initial: bool) {
+        let at = Instant::now();
+        let name = new.member.as_ref().map(|x| x.display_name().to_string());
+
+        for kind in voice_state_events(old, new, channel_id) {
+            let kind = match kind {
+                VoiceEventKind::Join if initial => VoiceEventKind::Present,
+                x => x,
+            };
+
+            let update = VoiceUpdate {
+                guild: guild_id,
+                update: VoiceUpdateType::VoiceState(VoiceEvent {
+                    user: new.user_id,
+                    name: name.clone(),
+                    kind,
+                    at,
+                }),
+            };
+
+            self.writer.send(update);
+        }
+    }
+
+    /// Logs a message if it was posted in the chat of the voice channel being recorded.
+    pub async fn log_chat(&self, ctx: &Context, guild_id: GuildId, msg: &Message) {
+        if !self.config.capture_chat {
+            return;
+        }
+
+        let sbird = songbird::get(ctx).await.expect("Songbird doesn't exist!");
+
+        let channel_id = match sbird.get(guild_id) {
+            Some(call) => call.lock().await.current_channel().map(|x| ChannelId::from(x.0)),
+            None => return,
+        };
+
+        if channel_id != Some(msg.channel_id) {
+            return;
+        }
+
+        let author = match msg.author_nick(ctx).await {
+            Some(nick) => nick,
+            None => msg.author.display_name().to_string(),
+        };
+
+        let chat_message = ChatMessage {
+            id: msg.id,
+            author_id: msg.author.id,
+            author,
+            timestamp: *msg.timestamp,
+            content: msg.content.clone(),
+            attachments: msg.attachments.iter().map(|x| x.url.clone()).collect(),
+        };
+
+        let update = VoiceUpdate {
+            guild: guild_id,
+            update: VoiceUpdateType::Chat(chat_message),
+        };
+
+        self.writer.send(update);
+    }
+
+    /// Saves the last `minutes` of the running recording as a separate zip.
+    pub async fn clip(&self, guild_id: GuildId, minutes: u64) -> Result<PathBuf, RecorderError> {
+        info!("[{guild_id}] Clipping the last {minutes} minute(s)");
+        self.writer.clip(guild_id, Duration::from_secs(minutes * 60)).await
+    }
+
+    /// Tidies up a clip once it has been offered to Discord: posted clips are removed, and ones that couldn't be
+    /// posted are kept as a zip, encrypted for guilds with keys. Returns the file that was kept.
+    pub async fn settle_clip(&self, guild_id: GuildId, zip_path: &Path, posted: bool) -> Option<PathBuf> {
+        self.writer.settle_clip(guild_id, zip_path, posted).await
+    }
+
+    /// Writes a recording from a packet dump captured earlier, without connecting to Discord.
+    pub async fn replay(&self, dump: &Path) -> Result<RecordingSummary, RecorderError> {
+        self.writer.replay(dump).await
+    }
+
+    /// Re-checks the files of a finished recording against the checksums written when it was zipped.
+    pub async fn verify(&self, guild_id: GuildId, id: &str) -> Result<Vec<ChecksumResult>, RecorderError> {
+        if id.is_empty() || id.contains(['/', '\\']) || id.starts_with('.') {
+            return Err(StateError::InvalidRecordingId(id.to_string()).into());
+        }
+
+        let dir = self.config.base_dir.join(guild_id.to_string()).join(id);
+        if !tokio::fs::try_exists(&dir).await.unwrap_or(false) {
+            return Err(StateError::RecordingNotFound(id.to_string()).into());
+        }
+
+        debug!("[{guild_id}] Verifying checksums in {}", dir.display());
+
+        tokio::task::spawn_blocking(move || verify_checksums(&dir)).await
+            .map_err(|source| RecorderError::Task { name: "Verification", source })?
+    }
+}
+
+impl TypeMapKey for Recorder {
+    type Value = Arc<Recorder>;
 }
 
 impl Drop for Recorder {
@@ -0,0 +1,62 @@
+use std::path::{Path, PathBuf};
+use serenity::async_trait;
+use crate::recorder::error::RecorderError;
+use crate::recorder::storage::{StorageBackend, StorageLocation};
+
+/// Keeps recordings on the local filesystem, copying them into `dir` if it differs from where they were written.
+#[derive(Debug)]
+pub struct LocalStorage {
+    dir: PathBuf,
+}
+
+impl LocalStorage {
+    /// Stores recordings in `dir`.
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalStorage {
+    async fn store(&self, local_path: &Path, key: &str) -> Result<StorageLocation, RecorderError> {
+        let target = self.dir.join(key);
+
+        if target == local_path {
+            return Ok(StorageLocation::Local(target));
+        }
+
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(|e| RecorderError::io("create directory", parent, e))?;
+        }
+
+        tokio::fs::copy(local_path, &target).await.map_err(|e| RecorderError::io("copy recording to", &target, e))?;
+
+        Ok(StorageLocation::Local(target))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn copies_into_dir_unless_already_there() {
+        let root = std::env::temp_dir().join(format!("disrecord_local_test_{}", std::process::id()));
+        let written = root.join("recordings/1/session/session.zip");
+        tokio::fs::create_dir_all(written.parent().unwrap()).await.unwrap();
+        tokio::fs::write(&written, b"zip").await.unwrap();
+
+        let in_place = LocalStorage::new(root.join("recordings"));
+        assert_eq!(in_place.store(&written, "1/session/session.zip").await.unwrap(), StorageLocation::Local(written.clone()));
+
+        let archive = LocalStorage::new(root.join("archive"));
+        let copied = root.join("archive/1/session/session.zip");
+        assert_eq!(archive.store(&written, "1/session/session.zip").await.unwrap(), StorageLocation::Local(copied.clone()));
+        assert_eq!(tokio::fs::read(&copied).await.unwrap(), b"zip");
+
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+}
\ No newline at end of file
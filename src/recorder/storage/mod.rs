@@ -0,0 +1,52 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::path::{Path, PathBuf};
+use serenity::async_trait;
+use crate::recorder::error::RecorderError;
+
+/// Storing recordings in a local directory.
+pub mod local;
+/// Uploading recordings to an S3 bucket.
+pub mod s3;
+/// Uploading recordings over SFTP.
+pub mod sftp;
+
+/// Where a finished recording ended up.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StorageLocation {
+    /// A path on the local disk.
+    Local(PathBuf),
+    /// An object in an S3 bucket.
+    S3 {
+        /// The bucket.
+        bucket: String,
+        /// The object key.
+        key: String,
+    },
+    /// A file on an SFTP server.
+    Sftp {
+        /// The server's host name.
+        host: String,
+        /// The path on the server.
+        path: String,
+    },
+}
+
+impl Display for StorageLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageLocation::Local(path) => write!(f, "{}", path.display()),
+            StorageLocation::S3 { bucket, key } => write!(f, "s3://{bucket}/{key}"),
+            StorageLocation::Sftp { host, path } => write!(f, "sftp://{host}/{}", path.trim_start_matches('/')),
+        }
+    }
+}
+
+/// A destination for finished recordings.
+///
+/// Tracks are always written and zipped locally under `base_dir` first; the finished zip is then handed
+/// to the backend under a key of the form `<guild_id>/<session>/<session>.zip`.
+#[async_trait]
+pub trait StorageBackend: Debug + Send + Sync {
+    /// Stores the file at `local_path` under `key`, returning where it ended up.
+    async fn store(&self, local_path: &Path, key: &str) -> Result<StorageLocation, RecorderError>;
+}
\ No newline at end of file
@@ -0,0 +1,156 @@
+use std::path::Path;
+use std::sync::Arc;
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectStore, WriteMultipart};
+use serenity::async_trait;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+use crate::recorder::error::{RecorderError, StorageError};
+use crate::recorder::storage::{StorageBackend, StorageLocation};
+
+/// Size of each part of a multipart upload.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+/// Maximum number of parts in flight at once.
+const MAX_CONCURRENT_PARTS: usize = 4;
+
+/// Settings for uploading to S3, from the `[storage.s3]` section of the config.
+#[derive(Clone, Debug)]
+pub struct S3Config {
+    /// Custom endpoint for S3-compatible services such as MinIO. Uses AWS when `None`.
+    pub endpoint: Option<String>,
+    /// Region of the bucket.
+    pub region: String,
+    /// Name of the bucket.
+    pub bucket: String,
+    /// Access key to authenticate with.
+    pub access_key_id: String,
+    /// Secret of the access key.
+    pub secret_access_key: String,
+    /// Prepended to every object key.
+    pub prefix: String,
+    /// Allow plain HTTP endpoints, e.g. for a local test server.
+    pub allow_http: bool,
+}
+
+/// Uploads recordings to an S3-compatible bucket.
+#[derive(Debug)]
+pub struct S3Storage {
+    bucket: String,
+    prefix: String,
+    store: Arc<dyn ObjectStore>,
+}
+
+impl S3Storage {
+    /// Creates a backend for the bucket in `config`.
+    pub fn new(config: S3Config) -> Result<Self, StorageError> {
+        let mut builder = AmazonS3Builder::new()
+            .with_region(&config.region)
+            .with_bucket_name(&config.bucket)
+            .with_access_key_id(&config.access_key_id)
+            .with_secret_access_key(&config.secret_access_key)
+            .with_allow_http(config.allow_http);
+
+        if let Some(endpoint) = &config.endpoint {
+            // Most self-hosted S3 implementations only support path-style requests.
+            builder = builder
+                .with_endpoint(endpoint)
+                .with_virtual_hosted_style_request(false);
+        }
+
+        let store = builder.build().map_err(|source| StorageError::S3 { action: "configure S3 storage".to_string(), source })?;
+
+        Ok(Self::with_store(config.bucket, config.prefix, Arc::new(store)))
+    }
+
+    /// Uploads through any `ObjectStore`, e.g. an in-memory one standing in for a real bucket.
+    pub fn with_store(bucket: String, prefix: String, store: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            bucket,
+            prefix,
+            store,
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Storage {
+    async fn store(&self, local_path: &Path, key: &str) -> Result<StorageLocation, RecorderError> {
+        let key = format!("{}{key}", self.prefix);
+        let s3_error = |action: &str, source| StorageError::S3 { action: format!("{action} s3://{}/{key}", self.bucket), source };
+
+        let object_path = ObjectPath::parse(&key).map_err(|e| s3_error("name", e.into()))?;
+
+        debug!("Uploading {} to s3://{}/{key}", local_path.display(), self.bucket);
+
+        let mut file = File::open(local_path).await.map_err(|e| RecorderError::io("open", local_path, e))?;
+
+        let upload = self.store.put_multipart(&object_path).await.map_err(|e| s3_error("start upload to", e))?;
+        let mut writer = WriteMultipart::new_with_chunk_size(upload, PART_SIZE);
+
+        let mut buffer = vec![0u8; PART_SIZE];
+        loop {
+            let read = match file.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(x) => x,
+                Err(e) => {
+                    _ = writer.abort().await;
+                    return Err(RecorderError::io("read", local_path, e));
+                }
+            };
+
+            if let Err(e) = writer.wait_for_capacity(MAX_CONCURRENT_PARTS).await {
+                _ = writer.abort().await;
+                return Err(s3_error("upload part of", e).into());
+            }
+
+            writer.write(&buffer[..read]);
+        }
+
+        writer.finish().await.map_err(|e| s3_error("finish upload to", e))?;
+
+        info!("Uploaded {} to s3://{}/{key}", local_path.display(), self.bucket);
+
+        Ok(StorageLocation::S3 {
+            bucket: self.bucket.clone(),
+            key,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn uploads_multipart_under_prefix() {
+        let dir = std::env::temp_dir().join(format!("disrecord_s3_test_{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        // Big enough to need more than one part.
+        let data = (0..PART_SIZE + 12345).map(|x| (x % 251) as u8).collect::<Vec<_>>();
+        let local_path = dir.join("recording.zip");
+        tokio::fs::write(&local_path, &data).await.unwrap();
+
+        let store = Arc::new(InMemory::new());
+        let storage = S3Storage::with_store("bucket".to_string(), "recordings/".to_string(), store.clone());
+
+        let location = storage.store(&local_path, "1/session/session.zip").await.unwrap();
+        assert_eq!(location, StorageLocation::S3 {
+            bucket: "bucket".to_string(),
+            key: "recordings/1/session/session.zip".to_string(),
+        });
+
+        let uploaded = store.get(&ObjectPath::from("recordings/1/session/session.zip")).await.unwrap().bytes().await.unwrap();
+        assert_eq!(uploaded.as_ref(), data.as_slice());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fails_on_missing_file() {
+        let storage = S3Storage::with_store("bucket".to_string(), String::new(), Arc::new(InMemory::new()));
+        assert!(storage.store(Path::new("/nonexistent/recording.zip"), "x.zip").await.is_err());
+    }
+}
\ No newline at end of file
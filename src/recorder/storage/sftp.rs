@@ -0,0 +1,164 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use serenity::async_trait;
+use ssh2::{CheckResult, KnownHostFileKind, Session, Sftp};
+use crate::recorder::error::{RecorderError, StorageError};
+use crate::recorder::storage::{StorageBackend, StorageLocation};
+
+/// Settings for uploading over SFTP, from the `[storage.sftp]` section of the config.
+#[derive(Clone, Debug)]
+pub struct SftpConfig {
+    /// Host name of the server.
+    pub host: String,
+    /// Port of the server.
+    pub port: u16,
+    /// User to log in as.
+    pub username: String,
+    /// Private key to authenticate with. Takes precedence over `password`.
+    pub private_key: Option<PathBuf>,
+    /// Password to authenticate with.
+    pub password: Option<String>,
+    /// OpenSSH `known_hosts` file to verify the server against. The host key is not checked when `None`.
+    pub known_hosts: Option<PathBuf>,
+    /// Directory on the server that keys are relative to.
+    pub remote_dir: String,
+}
+
+/// Uploads recordings to a server over SFTP.
+#[derive(Debug)]
+pub struct SftpStorage {
+    config: SftpConfig,
+}
+
+impl SftpStorage {
+    /// Creates a backend for the server in `config`. Fails if neither a key nor a password is given.
+    pub fn new(config: SftpConfig) -> Result<Self, StorageError> {
+        if config.private_key.is_none() && config.password.is_none() {
+            return Err(StorageError::Config("SFTP storage needs either a private key or a password".to_string()));
+        }
+
+        if config.known_hosts.is_none() {
+            warn!("SFTP host key for {} will not be verified!", config.host);
+        }
+
+        Ok(Self {
+            config,
+        })
+    }
+
+    fn connect(&self) -> Result<Sftp, StorageError> {
+        let config = &self.config;
+        let ssh_error = |action: &str| {
+            let action = action.to_string();
+            move |source| StorageError::Ssh { action, source }
+        };
+        let host_key_error = |problem| StorageError::HostKey { host: config.host.clone(), problem };
+
+        let tcp = TcpStream::connect((config.host.as_str(), config.port))
+            .map_err(|source| StorageError::Connect { addr: format!("{}:{}", config.host, config.port), source })?;
+
+        let mut session = Session::new().map_err(ssh_error("create SSH session"))?;
+        session.set_tcp_stream(tcp);
+        session.handshake().map_err(ssh_error("complete SSH handshake"))?;
+
+        if let Some(known_hosts_path) = &config.known_hosts {
+            let mut known_hosts = session.known_hosts().map_err(ssh_error("initialize known hosts"))?;
+            known_hosts.read_file(known_hosts_path, KnownHostFileKind::OpenSSH).map_err(ssh_error(&format!("read {}", known_hosts_path.display())))?;
+
+            let (key, _) = session.host_key().ok_or_else(|| host_key_error("was not provided by the server"))?;
+            match known_hosts.check_port(&config.host, config.port, key) {
+                CheckResult::Match => {}
+                CheckResult::NotFound => return Err(host_key_error("is not in the known hosts file")),
+                CheckResult::Mismatch => return Err(host_key_error("does NOT match the known hosts file!")),
+                CheckResult::Failure => return Err(host_key_error("could not be checked")),
+            }
+        }
+
+        match (&config.private_key, &config.password) {
+            (Some(private_key), password) => session.userauth_pubkey_file(&config.username, None, private_key, password.as_deref()),
+            (None, Some(password)) => session.userauth_password(&config.username, password),
+            (None, None) => unreachable!(),
+        }.map_err(ssh_error("authenticate"))?;
+
+        session.sftp().map_err(ssh_error("start SFTP subsystem"))
+    }
+
+    fn upload(&self, local_path: &Path, remote_path: &Path) -> Result<(), RecorderError> {
+        let sftp = self.connect()?;
+
+        // Create each missing parent directory in turn; SFTP has no recursive mkdir.
+        if let Some(parent) = remote_path.parent() {
+            let mut dir = PathBuf::new();
+            for component in parent.components() {
+                dir.push(component);
+                if sftp.stat(&dir).is_err() {
+                    sftp.mkdir(&dir, 0o755).map_err(|source| StorageError::Ssh { action: format!("create remote directory {}", dir.display()), source })?;
+                }
+            }
+        }
+
+        // Upload to a temporary name first so a partial upload is never mistaken for a finished one.
+        let partial_path = partial_path(remote_path);
+
+        let local_file = File::open(local_path).map_err(|e| RecorderError::io("open", local_path, e))?;
+        let remote_file = sftp.create(&partial_path)
+            .map_err(|source| StorageError::Ssh { action: format!("create remote file {}", partial_path.display()), source })?;
+
+        let mut reader = BufReader::new(local_file);
+        let mut writer = BufWriter::new(remote_file);
+        std::io::copy(&mut reader, &mut writer).map_err(|e| RecorderError::io("upload", local_path, e))?;
+        writer.flush().map_err(|e| RecorderError::io("upload", local_path, e))?;
+        drop(writer);
+
+        sftp.rename(&partial_path, remote_path, None)
+            .map_err(|source| StorageError::Ssh { action: format!("rename {}", partial_path.display()), source })?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StorageBackend for SftpStorage {
+    async fn store(&self, local_path: &Path, key: &str) -> Result<StorageLocation, RecorderError> {
+        let remote_path = Path::new(&self.config.remote_dir).join(key);
+
+        debug!("Uploading {} to sftp://{}/{}", local_path.display(), self.config.host, remote_path.display());
+
+        // libssh2 is blocking, so the whole upload runs off the async runtime.
+        let storage = SftpStorage {
+            config: self.config.clone(),
+        };
+        let upload_local = local_path.to_path_buf();
+        let upload_remote = remote_path.clone();
+        tokio::task::spawn_blocking(move || storage.upload(&upload_local, &upload_remote))
+            .await
+            .map_err(|source| RecorderError::Task { name: "SFTP upload", source })??;
+
+        info!("Uploaded {} to sftp://{}/{}", local_path.display(), self.config.host, remote_path.display());
+
+        Ok(StorageLocation::Sftp {
+            host: self.config.host.clone(),
+            path: remote_path.to_string_lossy().to_string(),
+        })
+    }
+}
+
+/// The name a file is uploaded under until it is complete, e.g. `x.zip.age.part`.
+fn partial_path(remote_path: &Path) -> PathBuf {
+    let mut name = remote_path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_path_keeps_every_extension() {
+        assert_eq!(partial_path(Path::new("out/1/s/s.zip")), PathBuf::from("out/1/s/s.zip.part"));
+        assert_eq!(partial_path(Path::new("out/1/s/s.zip.age")), PathBuf::from("out/1/s/s.zip.age.part"));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use serenity::all::UserId;
+
+/// Track name used when none is configured.
+pub const DEFAULT_TRACK_NAME: &str = "{index:02}_{display_name}_{user_id}.{ext}";
+
+/// Characters that are not allowed in a file name on at least one of the platforms we care about.
+const FORBIDDEN_CHARS: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+/// Device names that Windows refuses to use as a file name, regardless of extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+/// Maximum length (in characters) of a single substituted field.
+const MAX_FIELD_LENGTH: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    /// 1-based order in which the track was created within the recording.
+    Index,
+    DisplayName,
+    Username,
+    UserId,
+    Ssrc,
+    Ext,
+}
+
+impl Field {
+    fn from(name: &str) -> Option<Self> {
+        match name {
+            "index" => Some(Field::Index),
+            "display_name" => Some(Field::DisplayName),
+            "username" => Some(Field::Username),
+            "user_id" => Some(Field::UserId),
+            "ssrc" => Some(Field::Ssrc),
+            "ext" => Some(Field::Ext),
+            _ => None,
+        }
+    }
+
+    fn is_numeric(self) -> bool {
+        matches!(self, Field::Index | Field::UserId | Field::Ssrc)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Literal(String),
+    Field { field: Field, width: usize },
+}
+
+/// Values available to a track name template.
+#[derive(Debug)]
+pub struct TrackNameFields<'a> {
+    /// Position of the track in the recording, starting at 1.
+    pub index: usize,
+    /// The user's display name in the guild, if known.
+    pub display_name: Option<&'a str>,
+    /// The user's Discord username, if known.
+    pub username: Option<&'a str>,
+    /// The user's ID.
+    pub user_id: UserId,
+    /// The SSRC the track was received on.
+    pub ssrc: u32,
+    /// File extension, without the dot.
+    pub ext: &'a str,
+}
+
+/// A parsed file naming template such as `{index:02}_{display_name}_{user_id}.{ext}`.
+///
+/// Numeric fields (`index`, `user_id`, `ssrc`) accept a zero-padded width, e.g. `{index:03}`.
+/// Use `{{` and `}}` for literal braces.
+#[derive(Clone, Debug)]
+pub struct TrackNameTemplate {
+    parts: Vec<Part>,
+}
+
+impl TrackNameTemplate {
+    /// Parses `template`, returning a description of the problem if it isn't valid.
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => spec.push(c),
+                            None => return Err(format!("Unclosed placeholder in track name template: {template}")),
+                        }
+                    }
+
+                    let (name, width) = match spec.split_once(':') {
+                        None => (spec.as_str(), 0),
+                        Some((name, width)) => {
+                            let width = width.parse::<usize>().map_err(|_| format!("Invalid width for {{{name}}}: {width}"))?;
+                            (name, width)
+                        }
+                    };
+
+                    let field = Field::from(name).ok_or(format!("Unknown placeholder in track name template: {{{name}}}"))?;
+                    if width > 0 && !field.is_numeric() {
+                        return Err(format!("Placeholder {{{name}}} does not accept a width"));
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field { field, width });
+                }
+                '}' => return Err(format!("Unmatched '}}' in track name template: {template}")),
+                c if FORBIDDEN_CHARS.contains(&c) || c.is_control() => {
+                    return Err(format!("Track name template contains a forbidden character: {c:?}"));
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        if !parts.iter().any(|p| matches!(p, Part::Field { .. })) {
+            return Err("Track name template must contain at least one placeholder".to_string());
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// Renders a file name, sanitizing every substituted value so the result is safe on all platforms.
+    pub fn render(&self, fields: &TrackNameFields) -> String {
+        let mut name = String::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => name += literal,
+                Part::Field { field, width } => {
+                    let value = match field {
+                        Field::Index => format!("{:0width$}", fields.index),
+                        Field::UserId => format!("{:0width$}", fields.user_id.get()),
+                        Field::Ssrc => format!("{:0width$}", fields.ssrc),
+                        Field::DisplayName => {
+                            let fallback = fields.user_id.to_string();
+                            sanitize(fields.display_name.or(fields.username).unwrap_or(&fallback))
+                        }
+                        Field::Username => {
+                            let fallback = fields.user_id.to_string();
+                            sanitize(fields.username.unwrap_or(&fallback))
+                        }
+                        Field::Ext => sanitize(fields.ext),
+                    };
+                    name += &value;
+                }
+            }
+        }
+
+        let stem = name.split('.').next().unwrap_or_default();
+        if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem.trim_end())) {
+            name.insert(0, '_');
+        }
+
+        name
+    }
+}
+
+/// Inserts a numeric suffix before the extension, for resolving name collisions.
+pub fn with_suffix(name: &str, suffix: usize) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{stem}_{suffix}.{ext}"),
+        _ => format!("{name}_{suffix}"),
+    }
+}
+
+/// Lowercased names of every file reserved in a recording directory, so names differing only by case don't collide.
+#[derive(Debug, Default)]
+pub struct ReservedNames {
+    names: Mutex<HashSet<String>>,
+}
+
+impl ReservedNames {
+    /// Reserves `name` in `dir`, or the first variant of it with a numeric suffix that is neither reserved nor on disk.
+    pub async fn reserve(&self, dir: &Path, name: &str) -> PathBuf {
+        let mut candidate = name.to_string();
+        let mut suffix = 1;
+
+        loop {
+            let path = dir.join(&candidate);
+            let exists = tokio::fs::try_exists(&path).await.unwrap_or(false);
+
+            if !exists && self.names.lock().unwrap().insert(candidate.to_lowercase()) {
+                return path;
+            }
+
+            suffix += 1;
+            candidate = with_suffix(name, suffix);
+        }
+    }
+}
+
+/// Makes a single value safe for use as part of a file name on Windows, macOS and Linux.
+fn sanitize(value: &str) -> String {
+    let replaced = value.chars()
+        .map(|c| if FORBIDDEN_CHARS.contains(&c) || c.is_control() { '_' } else { c })
+        .take(MAX_FIELD_LENGTH)
+        .collect::<String>();
+
+    // Leading dots hide files on Unix, and Windows silently strips trailing dots and spaces.
+    let trimmed = replaced.trim_matches(|c: char| c == '.' || c.is_whitespace());
+
+    if trimmed.is_empty() {
+        "_".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reserved_names_never_collide() {
+        let dir = std::env::temp_dir().join(format!("disrecord_names_test_{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        tokio::fs::write(dir.join("on_disk.opus"), b"").await.unwrap();
+
+        let names = ReservedNames::default();
+
+        // A track named like another track's segment takes the name first, so the segment moves aside.
+        assert_eq!(names.reserve(&dir, "01_a.seg002.opus").await, dir.join("01_a.seg002.opus"));
+        assert_eq!(names.reserve(&dir, "01_a.seg002.opus").await, dir.join("01_a.seg002_2.opus"));
+        assert_eq!(names.reserve(&dir, "01_A.SEG002.opus").await, dir.join("01_A.SEG002_3.opus"));
+        assert_eq!(names.reserve(&dir, "on_disk.opus").await, dir.join("on_disk_2.opus"));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
\ No newline at end of file
@@ -1,14 +1,18 @@
-use crate::recorder::writer::{OpusUpdate, UserUpdate, VoiceUpdate, VoiceUpdateType};
+use crate::recorder::jitter_buffer::JitterBuffer;
+use crate::recorder::writer::muxer::opus_packet::OpusPacket;
+use crate::recorder::writer::{OpusUpdate, UserUpdate, VoiceUpdate, VoiceUpdateType, Writer};
 use dashmap::{DashMap};
 use serenity::async_trait;
 use serenity::model::id::UserId;
 use serenity::model::voice_gateway::payload::Speaking;
-use songbird::packet::FromPacket;
+use songbird::packet::{FromPacket, Packet};
 use songbird::{EventContext, EventHandler};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use serenity::all::{Cache, CacheHttp, Context, GuildId, Http};
-use songbird::packet::rtp::RtpExtensionPacket;
-use tokio::sync::mpsc::Sender;
+use songbird::driver::CryptoMode;
+use songbird::events::context_data::RtpData;
+use songbird::packet::rtp::{RtpExtensionPacket, RtpPacket};
 
 #[derive(Clone, Debug)]
 struct CtxHolder {
@@ -35,6 +39,57 @@ impl From<&Context> for CtxHolder {
     }
 }
 
+/// Encryption modes we know the packet layout of.
+/// Discord encrypts the RTP extension body along with the Opus payload, and appends a tag and nonce after it.
+const SUPPORTED_CRYPTO_MODES: [CryptoMode; 2] = [CryptoMode::Aes256Gcm, CryptoMode::XChaCha20Poly1305];
+
+/// Extracts the Opus payload from a decrypted RTP packet, validating that the result looks like Opus.
+fn extract_opus(rtp_data: &RtpData) -> Result<Vec<u8>, String> {
+    opus_payload(&rtp_data.packet, rtp_data.payload_offset, rtp_data.payload_end_pad)
+}
+
+/// Cuts the Opus payload out of a raw RTP packet.
+///
+/// For voice ticks, songbird gives the payload as the range `payload_offset..payload_end` of the RTP body,
+/// which still includes the (decrypted) extension block but not the encryption tag and nonce.
+fn opus_payload(packet: &[u8], payload_offset: usize, payload_end: usize) -> Result<Vec<u8>, String> {
+    let rtp = RtpPacket::new(packet).ok_or("RTP header is truncated")?;
+    let body = rtp.payload();
+
+    if payload_offset > payload_end || payload_end > body.len() {
+        return Err(format!("Payload range {payload_offset}..{payload_end} is outside the {} byte RTP body", body.len()));
+    }
+
+    let tail = body.len() - payload_end;
+    if !SUPPORTED_CRYPTO_MODES.iter().any(|mode| mode.payload_suffix_len() == tail) {
+        return Err(format!("Unexpected {tail} byte trailer, has the encryption mode changed?"));
+    }
+
+    let payload = &body[payload_offset..payload_end];
+
+    // The extension body is decrypted in place, so its length can be read from the extension header.
+    let head = if rtp.get_extension() != 0 {
+        let ext = RtpExtensionPacket::new(payload).ok_or("RTP extension header is truncated")?;
+        ext.packet().len() - ext.payload().len()
+    } else {
+        0
+    };
+
+    let mut opus_data = payload.get(head..).ok_or(format!("RTP payload is too short ({} bytes) for a {head} byte extension", payload.len()))?;
+
+    if rtp.get_padding() != 0 {
+        let padding = *opus_data.last().ok_or("Padded RTP packet has no payload")? as usize;
+        if padding == 0 || padding > opus_data.len() {
+            return Err(format!("Invalid RTP padding length: {padding}"));
+        }
+        opus_data = &opus_data[..opus_data.len() - padding];
+    }
+
+    OpusPacket::parse(opus_data).map_err(|e| format!("Payload is not a valid Opus packet: {e}"))?;
+
+    Ok(opus_data.to_owned())
+}
+
 #[derive(Clone, Debug)]
 pub struct VoiceReceiver {
     pub inner: Arc<InnerReceiver>,
@@ -43,22 +98,73 @@ pub struct VoiceReceiver {
 #[derive(Debug)]
 pub struct InnerReceiver {
     guild_id: GuildId,
-    voice_tx: Sender<VoiceUpdate>,
+    writer: Arc<Writer>,
     ssrc_users: DashMap<u32, UserId>,
+    jitter_buffers: DashMap<u32, JitterBuffer<OpusUpdate>>,
+    jitter_buffer_depth: usize,
+    payload_errors: AtomicUsize,
     ctx_holder: CtxHolder,
 }
 
 impl VoiceReceiver {
-    pub async fn new(guild_id: GuildId, ctx: &Context, voice_tx: Sender<VoiceUpdate>) -> Self {
+    pub async fn new(guild_id: GuildId, ctx: &Context, writer: Arc<Writer>, jitter_buffer_depth: usize) -> Self {
         Self {
             inner: Arc::new(InnerReceiver {
                 guild_id,
-                voice_tx,
+                writer,
                 ssrc_users: DashMap::new(),
+                jitter_buffers: DashMap::new(),
+                jitter_buffer_depth,
+                payload_errors: AtomicUsize::new(0),
                 ctx_holder: CtxHolder::from(ctx),
             }),
         }
     }
+
+    /// Releases every packet still held in the jitter buffers into the writer's queue, which never drops them.
+    /// Called once the call has been left, so the last packets of each speaker aren't lost.
+    pub fn flush(&self) {
+        let ssrcs = self.inner.jitter_buffers.iter().map(|x| *x.key()).collect::<Vec<_>>();
+        let packets = ssrcs.into_iter().flat_map(|ssrc| self.inner.flush_ssrc(ssrc)).collect::<Vec<_>>();
+
+        if !packets.is_empty() {
+            debug!("[{}] Flushing {} buffered packet(s)", self.inner.guild_id, packets.len());
+            self.inner.writer.send_without_dropping(VoiceUpdate {
+                guild: self.inner.guild_id,
+                update: VoiceUpdateType::Opus(packets),
+            });
+        }
+    }
+}
+
+impl InnerReceiver {
+    /// Empties and forgets the jitter buffer of an SSRC, returning whatever it was still holding.
+    fn flush_ssrc(&self, ssrc: u32) -> Vec<OpusUpdate> {
+        match self.jitter_buffers.remove(&ssrc) {
+            Some((_, mut jitter_buffer)) => {
+                log_stats(self.guild_id, ssrc, &jitter_buffer);
+                jitter_buffer.flush()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Queues the packets still buffered for an SSRC, so they are written before the SSRC is bound to someone else.
+    fn send_flushed(&self, ssrc: u32) {
+        let packets = self.flush_ssrc(ssrc);
+        if !packets.is_empty() {
+            self.writer.send_without_dropping(VoiceUpdate {
+                guild: self.guild_id,
+                update: VoiceUpdateType::Opus(packets),
+            });
+        }
+    }
+}
+
+fn log_stats(guild_id: GuildId, ssrc: u32, jitter_buffer: &JitterBuffer<OpusUpdate>) {
+    let stats = jitter_buffer.stats();
+    info!("[{guild_id}] SSRC {ssrc} packet stats: {} received, {} reordered, {} duplicate, {} late, {} lost",
+        stats.received, stats.reordered, stats.duplicates, stats.late, stats.lost);
 }
 
 #[async_trait]
@@ -70,13 +176,21 @@ impl EventHandler for VoiceReceiver {
             Ctx::SpeakingStateUpdate(Speaking { ssrc, user_id, .. }) => {
                 if let Some(user) = user_id {
                     let user = UserId::from(user.0);
-                    let username = match user.to_user(&self.inner.ctx_holder).await {
-                        Ok(u) => {
-                            Some(u.name)
+                    let (username, display_name) = match self.inner.guild_id.member(&self.inner.ctx_holder, user).await {
+                        Ok(m) => {
+                            (Some(m.user.name.clone()), Some(m.display_name().to_string()))
                         }
                         Err(e) => {
-                            warn!("[{}] <{user}> Failed to get username: {e:?}", self.inner.guild_id);
-                            None
+                            warn!("[{}] <{user}> Failed to get guild member: {e:?}", self.inner.guild_id);
+                            match user.to_user(&self.inner.ctx_holder).await {
+                                Ok(u) => {
+                                    (Some(u.name.clone()), Some(u.display_name().to_string()))
+                                }
+                                Err(e) => {
+                                    warn!("[{}] <{user}> Failed to get username: {e:?}", self.inner.guild_id);
+                                    (None, None)
+                                }
+                            }
                         }
                     };
 
@@ -84,22 +198,33 @@ impl EventHandler for VoiceReceiver {
                     match old_ssrc {
                         None => {
                             debug!("[{}] Found new user {user} with SSRC {ssrc}", self.inner.guild_id);
+
+                            // A user who rejoined won't be heard on their old SSRC again.
+                            let old_ssrcs = self.inner.ssrc_users.iter()
+                                .filter(|x| *x.value() == user && x.key() != ssrc)
+                                .map(|x| *x.key())
+                                .collect::<Vec<_>>();
+                            for old_ssrc in old_ssrcs {
+                                self.inner.send_flushed(old_ssrc);
+                            }
+
                             let update_data = VoiceUpdate {
                                 guild: self.inner.guild_id,
-                                update: VoiceUpdateType::User(UserUpdate { user, username }),
+                                update: VoiceUpdateType::User(UserUpdate { user, username, display_name, ssrc: *ssrc }),
                             };
-                            self.inner.voice_tx.send(update_data).await.unwrap();
+                            self.inner.writer.send(update_data);
                         }
                         Some(old_user) => {
                             if old_user == user {
                                 debug!("[{}] SSRC {ssrc} was refreshed for user {user}", self.inner.guild_id);
                             } else {
                                 warn!("[{}] SSRC {ssrc} reused! Was {old_user}, now {user}", self.inner.guild_id);
+                                self.inner.send_flushed(*ssrc);
                                 let update_data = VoiceUpdate {
                                     guild: self.inner.guild_id,
-                                    update: VoiceUpdateType::User(UserUpdate { user, username }),
+                                    update: VoiceUpdateType::User(UserUpdate { user, username, display_name, ssrc: *ssrc }),
                                 };
-                                self.inner.voice_tx.send(update_data).await.unwrap();
+                                self.inner.writer.send(update_data);
                             }
                         }
                     }
@@ -117,30 +242,40 @@ impl EventHandler for VoiceReceiver {
                             Some(user) => {
                                 let rtp = rtp_data.rtp().from_packet();
 
-                                // let head = rtp_data.payload_offset;
-                                // let tail = rtp_data.payload_end_pad;
-                                // let opus_data = rtp.payload[head..rtp.payload.len() - tail].to_owned();
-
-                                // Hack until I can fix Songbird's underlying sizing issues.
-                                let ext_pkt = RtpExtensionPacket::new(&rtp.payload).unwrap();
-                                let ext = ext_pkt.from_packet();
-                                let payload = ext.payload;
+                                let opus_data = match extract_opus(rtp_data) {
+                                    Ok(x) => x,
+                                    Err(e) => {
+                                        let errors = self.inner.payload_errors.fetch_add(1, Ordering::Relaxed) + 1;
+                                        if errors <= 10 || errors.is_multiple_of(1000) {
+                                            error!("[{}] <{}> Dropping packet with unusable payload ({errors} so far): {e}", self.inner.guild_id, *user);
+                                            error!("[{}] <{}> Packet: {:02x?}", self.inner.guild_id, *user, rtp_data.rtp().packet());
+                                        }
+                                        continue;
+                                    }
+                                };
 
-                                // 20 is constant with current Discord encryption scheme.
-                                let opus_data = payload[..payload.len() - 20].to_owned();
-                                // trace!("rtp: {:02x?}", rtp_data.rtp().packet());
-                                // trace!("ext: {:02x?}", rtp.payload);
-                                // trace!("pay: {:02x?}", payload);
-                                // trace!("opus: {:02x?}", opus_data);
+                                let sequence = rtp.sequence.into();
 
                                 let opus_update = OpusUpdate {
                                     user: *user,
                                     opus_data,
+                                    timestamp: rtp.timestamp.into(),
+                                    sequence,
                                 };
 
-                                update_data.push(opus_update);
+                                let mut jitter_buffer = self.inner.jitter_buffers.entry(*ssrc)
+                                    .or_insert_with(|| JitterBuffer::new(self.inner.jitter_buffer_depth));
+                                update_data.extend(jitter_buffer.push(sequence, opus_update));
                             }
                         }
+                    } else if let Some(mut jitter_buffer) = self.inner.jitter_buffers.get_mut(ssrc) {
+                        update_data.extend(jitter_buffer.tick());
+                    }
+                }
+
+                for ssrc in &voice_data.silent {
+                    if let Some(mut jitter_buffer) = self.inner.jitter_buffers.get_mut(ssrc) {
+                        update_data.extend(jitter_buffer.tick());
                     }
                 }
 
@@ -149,7 +284,7 @@ impl EventHandler for VoiceReceiver {
                     update: VoiceUpdateType::Opus(update_data),
                 };
 
-                self.inner.voice_tx.send(voice_update).await.unwrap();
+                self.inner.writer.send(voice_update);
             },
             _ => {
                 // We won't be registering this struct for any more event classes.
@@ -170,5 +305,97 @@ impl Drop for VoiceReceiver {
 impl Drop for InnerReceiver {
     fn drop(&mut self) {
         trace!("InnerReceiver::drop");
+
+        for entry in self.jitter_buffers.iter() {
+            log_stats(self.guild_id, *entry.key(), &entry);
+        }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::aead::{AeadInPlace, KeyInit};
+    use aes_gcm::Aes256Gcm;
+    use chacha20poly1305::XChaCha20Poly1305;
+
+    /// A 20 ms CELT frame of silence.
+    const OPUS: [u8; 3] = [0xf8, 0xff, 0xfe];
+    const HEADER_LEN: usize = 12;
+    /// `0xBEDE` profile and a length of one 32-bit word, sent in the clear.
+    const EXT_PREAMBLE: [u8; 4] = [0xbe, 0xde, 0x00, 0x01];
+    /// One one-byte extension element, padded to a word. Encrypted along with the Opus payload.
+    const EXT_BODY: [u8; 4] = [0x10, 0xab, 0x00, 0x00];
+    const NONCE: [u8; 4] = [0x00, 0x00, 0x12, 0x34];
+
+    /// Builds a packet the way Discord sends it in the `*_rtpsize` modes, then decrypts it in place like songbird does.
+    /// Returns the packet and the payload end that songbird reports for a voice tick.
+    fn received_packet(mode: CryptoMode) -> (Vec<u8>, usize) {
+        let mut packet = vec![0x90, 0x78, 0x00, 0x01, 0x00, 0x00, 0x03, 0xc0, 0x00, 0x00, 0x00, 0x2a];
+        packet.extend(EXT_PREAMBLE);
+        let aad_len = packet.len();
+
+        let mut encrypted = [EXT_BODY.as_slice(), OPUS.as_slice()].concat();
+        let key = [7u8; 32];
+
+        let tag = match mode {
+            CryptoMode::Aes256Gcm => {
+                let mut nonce = aes_gcm::Nonce::default();
+                nonce[..4].copy_from_slice(&NONCE);
+                Aes256Gcm::new_from_slice(&key).unwrap().encrypt_in_place_detached(&nonce, &packet, &mut encrypted).unwrap().to_vec()
+            }
+            CryptoMode::XChaCha20Poly1305 => {
+                let mut nonce = chacha20poly1305::XNonce::default();
+                nonce[..4].copy_from_slice(&NONCE);
+                XChaCha20Poly1305::new_from_slice(&key).unwrap().encrypt_in_place_detached(&nonce, &packet, &mut encrypted).unwrap().to_vec()
+            }
+            _ => unreachable!(),
+        };
+        assert_ne!(&encrypted[EXT_BODY.len()..], OPUS.as_slice());
+
+        packet.extend(&encrypted);
+        packet.extend(&tag);
+        packet.extend(NONCE);
+        assert_eq!(packet.len() - HEADER_LEN - (EXT_PREAMBLE.len() + encrypted.len()), mode.payload_suffix_len());
+
+        // Songbird decrypts the ciphertext in place, leaving the tag and nonce behind it.
+        let ciphertext_range = aad_len..aad_len + encrypted.len();
+        let (aad, rest) = packet.split_at_mut(aad_len);
+        let ciphertext = &mut rest[..ciphertext_range.len()];
+        match mode {
+            CryptoMode::Aes256Gcm => {
+                let mut nonce = aes_gcm::Nonce::default();
+                nonce[..4].copy_from_slice(&NONCE);
+                Aes256Gcm::new_from_slice(&key).unwrap().decrypt_in_place_detached(&nonce, aad, ciphertext, tag.as_slice().into()).unwrap();
+            }
+            CryptoMode::XChaCha20Poly1305 => {
+                let mut nonce = chacha20poly1305::XNonce::default();
+                nonce[..4].copy_from_slice(&NONCE);
+                XChaCha20Poly1305::new_from_slice(&key).unwrap().decrypt_in_place_detached(&nonce, aad, ciphertext, tag.as_slice().into()).unwrap();
+            }
+            _ => unreachable!(),
+        }
+
+        // As in songbird's `SsrcState::get_voice_tick`: an end index into the body, not a trailer length.
+        let body_len = packet.len() - HEADER_LEN;
+        (packet, body_len - mode.payload_suffix_len())
+    }
+
+    #[test]
+    fn extracts_aes_gcm_payload() {
+        let (packet, payload_end) = received_packet(CryptoMode::Aes256Gcm);
+        assert_eq!(opus_payload(&packet, 0, payload_end).unwrap(), OPUS);
+    }
+
+    #[test]
+    fn extracts_xchacha_payload() {
+        let (packet, payload_end) = received_packet(CryptoMode::XChaCha20Poly1305);
+        assert_eq!(opus_payload(&packet, 0, payload_end).unwrap(), OPUS);
+    }
+
+    #[test]
+    fn rejects_trailer_of_unknown_mode() {
+        let (packet, payload_end) = received_packet(CryptoMode::Aes256Gcm);
+        assert!(opus_payload(&packet, 0, payload_end - 4).is_err());
+    }
+}
\ No newline at end of file
@@ -1,45 +1,115 @@
 use std::collections::HashSet;
 use crate::recorder::writer::stream_writer::StreamWriter;
-use crate::recorder::writer::VoiceUpdateType;
-use crate::recorder::{RecordingMetadata, RecordingSummary};
+use crate::recorder::writer::{ChatMessage, UserUpdate, VoiceUpdateType};
+use crate::recorder::writer::chat_log::write_chat_log;
+use crate::recorder::writer::timeline::{write_timeline, VoiceEvent, VoiceEventKind};
+use crate::recorder::{RecorderConfig, RecordingMetadata, RecordingSummary, StoredRecording, ZipProgress};
+use crate::recorder::track_name::{ReservedNames, TrackNameFields};
 use dashmap::{DashMap, DashSet};
-use serenity::all::UserId;
+use serenity::all::{GuildId, UserId};
+use age::x25519::Recipient;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use chrono::Utc;
 use tokio::sync::oneshot::channel;
+use tokio::sync::watch;
+use crate::recorder::writer::repair::repair_track;
 use crate::recorder::writer::zipper::zip_files;
+use crate::recorder::writer::manifest::Manifest;
+use crate::recorder::writer::clip::write_clip;
+use crate::recorder::writer::muxer::opus_toc::GRANULE_SAMPLE_RATE;
+use std::time::{Duration, Instant};
+use crate::recorder::encryption::{encrypt_file, EncryptionConfig, ENCRYPTED_EXT};
+use crate::recorder::checksums::{write_checksums, write_digest_file, CHECKSUMS_FILE, DIGEST_EXT};
+use crate::recorder::storage::StorageBackend;
+use crate::recorder::durability::sync_dir;
+use crate::recorder::metrics::RecorderMetrics;
+use crate::recorder::error::{RecorderError, StateError};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
+/// Writes one recording: a track per speaker, plus the chat log, timeline and manifest once it is finished.
 #[derive(Debug)]
 pub struct CallWriter {
     metadata: RecordingMetadata,
     streams: DashMap<UserId, Arc<StreamWriter>>,
+    /// Streams that were finished early because their user rejoined under a new SSRC.
+    retired_streams: Mutex<Vec<Arc<StreamWriter>>>,
     known_users: DashSet<UserId>,
-    tick_count: Mutex<usize>,
+    config: RecorderConfig,
+    /// Names of every track and segment file created so far.
+    file_names: Arc<ReservedNames>,
+    /// Number of tracks created so far.
+    track_count: AtomicUsize,
+    metrics: Arc<RecorderMetrics>,
+    chat: Mutex<Vec<ChatMessage>>,
+    timeline: Mutex<Vec<VoiceEvent>>,
+    /// Everyone seen in the channel, whether or not they spoke.
+    present_users: DashSet<UserId>,
+    write_errors: AtomicU64,
+    /// The first error hit while writing, reported when the recording is finished.
+    first_error: Mutex<Option<RecorderError>>,
 }
 
 impl CallWriter {
-    pub fn new(metadata: RecordingMetadata) -> Self {
+    /// Creates the writer of a new recording. Nothing is written until the first update is handled.
+    pub fn new(metadata: RecordingMetadata, config: &RecorderConfig, metrics: Arc<RecorderMetrics>) -> Self {
         Self {
             metadata,
             streams: DashMap::new(),
+            retired_streams: Mutex::new(Vec::new()),
             known_users: DashSet::new(),
-            tick_count: Mutex::new(0),
+            config: config.clone(),
+            file_names: Arc::new(ReservedNames::default()),
+            track_count: AtomicUsize::new(0),
+            metrics,
+            chat: Mutex::new(Vec::new()),
+            timeline: Mutex::new(Vec::new()),
+            present_users: DashSet::new(),
+            write_errors: AtomicU64::new(0),
+            first_error: Mutex::new(None),
         }
     }
 
-    pub async fn push(&self, update_data: VoiceUpdateType) {
-        match update_data {
-            VoiceUpdateType::Opus(opus_update) => {
-                let tick_count = {
-                    let mut counter = self.tick_count.lock().unwrap();
-                    *counter += 1;
-                    *counter
-                };
+    /// Notes an error hit while writing, so the recording can carry on and report it when finished.
+    pub fn record_error(&self, e: RecorderError) {
+        let errors = self.write_errors.fetch_add(1, Ordering::Relaxed) + 1;
+        if errors <= 10 || errors.is_multiple_of(1000) {
+            error!("[{}] Error while recording ({errors} so far): {e}", self.metadata.guild_id);
+        }
+
+        self.first_error.lock().unwrap().get_or_insert(e);
+    }
+
+    /// Picks a file path for a new track, adding a numeric suffix if the name is already taken.
+    async fn next_track_path(&self, user_update: &UserUpdate) -> PathBuf {
+        let name = self.config.track_name.render(&TrackNameFields {
+            index: self.track_count.fetch_add(1, Ordering::Relaxed) + 1,
+            display_name: user_update.display_name.as_deref(),
+            username: user_update.username.as_deref(),
+            user_id: user_update.user,
+            ssrc: user_update.ssrc,
+            ext: "opus",
+        });
 
-                let silent_users = self.known_users.clone();
+        let path = self.file_names.reserve(&self.metadata.output_dir, &name).await;
+        if path.file_name().is_none_or(|x| x != name.as_str()) {
+            warn!("[{}] <{}> Track name {name} is taken, using {}", self.metadata.guild_id, user_update.user, path.display());
+        }
 
+        path
+    }
+
+    /// Describes the recording being written.
+    pub fn metadata(&self) -> &RecordingMetadata {
+        &self.metadata
+    }
+
+    /// Writes an update that arrived at `at`, in samples since the recording started.
+    pub async fn push(&self, at: u64, update_data: VoiceUpdateType) -> Result<(), RecorderError> {
+        match update_data {
+            VoiceUpdateType::Opus(opus_update) => {
                 for opus_update in opus_update {
-                    let user = opus_update.user.clone();
+                    let user = opus_update.user;
 
                     let stream = match self.streams.get(&user) {
                         Some(stream) => stream.clone(),
@@ -49,66 +119,361 @@ impl CallWriter {
                         }
                     };
 
-                    silent_users.remove(&user);
-                    stream.push(opus_update.opus_data.as_slice(), tick_count).await;
+                    self.metrics.voice_packet(self.metadata.guild_id);
+
+                    // A failing track shouldn't stop the others from being written.
+                    if let Err(e) = stream.push(opus_update.opus_data.as_slice(), opus_update.timestamp, opus_update.sequence, at).await {
+                        self.record_error(e);
+                    }
                 }
 
-                for user in silent_users {
-                    let stream = self.streams.get(&user).unwrap();
-                    stream.push_silence(tick_count).await;
+                Ok(())
+            }
+            VoiceUpdateType::Chat(message) => {
+                trace!("[{}] <{}> Logging chat message {}", self.metadata.guild_id, message.author_id, message.id);
+                self.chat.lock().unwrap().push(message);
+                Ok(())
+            }
+            VoiceUpdateType::VoiceState(event) => {
+                debug!("[{}] <{}> Voice state: {:?}", self.metadata.guild_id, event.user, event.kind);
+                if matches!(event.kind, VoiceEventKind::Present | VoiceEventKind::Join | VoiceEventKind::MoveIn) {
+                    self.present_users.insert(event.user);
                 }
+                self.timeline.lock().unwrap().push(event);
+                Ok(())
             }
             VoiceUpdateType::User(user_update) => {
                 let user = user_update.user;
 
-                let new_stream = StreamWriter::new(self.metadata.guild_id, user, user_update.username, self.metadata.output_dir.clone()).await;
-                match new_stream {
-                    None => {
-                        error!("[{}] <{}> Failed to create new stream!", self.metadata.guild_id, user);
-                        return;
+                if let Some(old_stream) = self.streams.get(&user).map(|x| x.clone()) {
+                    if old_stream.ssrc() == user_update.ssrc {
+                        debug!("[{}] <{user}> Already have a stream for SSRC {}", self.metadata.guild_id, user_update.ssrc);
+                        return Ok(());
                     }
-                    Some(new_stream) => {
-                        let new_stream = Arc::new(new_stream);
-
-                        let tick_count = *self.tick_count.lock().unwrap();
-                        new_stream.fill_silence(tick_count).await;
 
-                        self.streams.insert(user, new_stream.clone());
-                        self.known_users.insert(user);
+                    // Keep the old track intact and start a separate file for the new SSRC.
+                    info!("[{}] <{user}> Rejoined with SSRC {} (was {}), starting a new track", self.metadata.guild_id, user_update.ssrc, old_stream.ssrc());
+                    let res = old_stream.finish().await;
+                    self.retired_streams.lock().unwrap().push(old_stream);
+                    if let Err(e) = res {
+                        self.record_error(e);
                     }
                 }
+
+                let track_path = self.next_track_path(&user_update).await;
+
+                let new_stream = StreamWriter::new(&self.metadata, &user_update, track_path, &self.config, self.file_names.clone(), self.metrics.clone()).await?;
+
+                self.streams.insert(user, Arc::new(new_stream));
+                self.known_users.insert(user);
+                Ok(())
             }
         }
     }
 
-    pub async fn finish(&self) -> Option<RecordingSummary> {
+    /// Saves the last `duration` of every track into a zip of its own, leaving the recording running.
+    pub async fn clip(&self, duration: Duration) -> Result<PathBuf, RecorderError> {
+        let guild_id = self.metadata.guild_id;
+
+        if duration > self.config.clip_history {
+            return Err(StateError::ClipTooLong { max_minutes: self.config.clip_history.as_secs() / 60 }.into());
+        }
+
+        let end = self.metadata.clock.samples();
+        let start = end.saturating_sub((duration.as_secs_f64() * GRANULE_SAMPLE_RATE as f64) as u64);
+
+        let clip_name = format!("{}_clip_{}", self.metadata.output_dir_name, Utc::now().format(self.config.subdir_fmt.as_str()));
+        let clip_dir = self.config.base_dir.join(guild_id.to_string()).join("clips").join(&clip_name);
+        tokio::fs::create_dir_all(&clip_dir).await.map_err(|e| RecorderError::io("create directory", &clip_dir, e))?;
+
+        debug!("[{guild_id}] Clipping samples {start}..{end} into {}", clip_dir.display());
+
+        let retired_streams = self.retired_streams.lock().unwrap().clone();
+        let streams = self.streams.iter().map(|x| x.clone()).chain(retired_streams).collect::<Vec<_>>();
+
+        let mut clipped = 0;
+        for stream in streams {
+            let packets = stream.history_since(start);
+            if packets.is_empty() {
+                continue;
+            }
+
+            let file_name = stream.file_path().file_name().map(|x| x.to_os_string()).unwrap_or_default();
+            let path = clip_dir.join(file_name);
+            let comments = stream.comments().to_vec();
+
+            let res = tokio::task::spawn_blocking(move || write_clip(&path, comments, &packets, start, end)).await;
+            match res {
+                Ok(Ok(_)) => clipped += 1,
+                Ok(Err(e)) => error!("[{guild_id}] <{}> Failed to write clip: {e}", stream.user_id()),
+                Err(e) => error!("[{guild_id}] <{}> Clip task failed: {e:?}", stream.user_id()),
+            }
+        }
+
+        let res = if clipped == 0 {
+            Err(StateError::NothingToClip.into())
+        } else {
+            let (progress_tx, _) = watch::channel(ZipProgress::default());
+            zip_files(clip_dir.clone(), format!("{clip_name}.zip"), guild_id, progress_tx).await
+        };
+
+        // A clip that failed has nothing worth keeping, and its tracks shouldn't be left in plaintext.
+        if res.is_err() {
+            match tokio::fs::remove_dir_all(&clip_dir).await {
+                Ok(_) => debug!("[{guild_id}] Removed failed clip {}", clip_dir.display()),
+                Err(e) => warn!("[{guild_id}] Failed to remove failed clip {}: {e}", clip_dir.display()),
+            }
+        }
+
+        res
+    }
+
+    /// Finishes every track and writes the recording's timeline, chat log and manifest.
+    ///
+    /// The returned summary carries the zip, which is made and stored in the background.
+    pub async fn finish(&self) -> RecordingSummary {
         debug!("[{}] Finishing CallWriter!", self.metadata.guild_id);
+
+        // Pad every track out to the same length so they line up when mixed.
+        let end = self.metadata.clock.samples();
         for stream in &self.streams {
-            stream.finish().await;
+            let res = stream.pad_to(end).await;
+            if let Err(e) = res.and(stream.finish().await) {
+                self.record_error(e);
+            }
+        }
+
+        let stats = self.metrics.sync.stats();
+        info!("[{}] Disk syncs so far: {} ({} failed), mean {:?}, max {:?}", self.metadata.guild_id, stats.syncs, stats.failures, stats.mean(), stats.max);
+
+        let ended = self.metadata.clock.now_utc(self.metadata.started);
+
+        let retired_streams = self.retired_streams.lock().unwrap().clone();
+        let all_streams = self.streams.iter().map(|x| x.clone()).chain(retired_streams).collect::<Vec<_>>();
+
+        let manifest = Manifest::new(&self.metadata, ended, &all_streams);
+        let chat = std::mem::take(&mut *self.chat.lock().unwrap());
+        let chat_started = self.metadata.started;
+        let timeline = std::mem::take(&mut *self.timeline.lock().unwrap());
+        let epoch = self.metadata.epoch;
+
+        let mut repairs = Vec::new();
+        if self.config.repair_lost_packets {
+            for segment in all_streams.iter().flat_map(|x| x.segments()) {
+                if !segment.losses.is_empty() {
+                    repairs.push((segment.path, segment.losses));
+                }
+            }
         }
 
         self.streams.clear();
+        self.retired_streams.lock().unwrap().clear();
 
         let mut known_users = HashSet::new();
         for user in self.known_users.iter() {
             known_users.insert(*user);
         }
 
+        let silent_users = self.present_users.iter().map(|x| *x).filter(|x| !known_users.contains(x)).collect();
+
         let (zip_tx, zip_rx) = channel();
+        let (progress_tx, progress_rx) = watch::channel(ZipProgress::default());
 
         let zip_path = self.metadata.output_dir.clone();
         let zip_name = format!("{}.zip", self.metadata.output_dir_name);
         let zip_guild_id = self.metadata.guild_id.clone();
+        let storage_key = format!("{zip_guild_id}/{}/{zip_name}", self.metadata.output_dir_name);
+        let storage = self.config.storage.clone();
+        let encryption = self.config.encryption.clone();
+        self.metrics.remove_streams(self.metadata.guild_id);
+        let metrics = self.metrics.clone();
         tokio::spawn(async move {
-            zip_files(zip_path, zip_name, zip_guild_id, zip_tx).await;
+            for (track_path, losses) in repairs {
+                let res = tokio::task::spawn_blocking(move || repair_track(&track_path, &losses, zip_guild_id)).await;
+                match res {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => error!("[{zip_guild_id}] Failed to repair track: {e}"),
+                    Err(e) => error!("[{zip_guild_id}] Track repair task failed: {e:?}"),
+                }
+            }
+
+            // Nothing has been written yet if nobody spoke.
+            if let Err(e) = tokio::fs::create_dir_all(&zip_path).await {
+                error!("[{zip_guild_id}] Failed to create {}: {e}", zip_path.display());
+            }
+
+            if let Err(e) = manifest.write(&zip_path).await {
+                error!("[{zip_guild_id}] {e}");
+            }
+
+            if !chat.is_empty() && let Err(e) = write_chat_log(&zip_path, chat_started, &chat).await {
+                error!("[{zip_guild_id}] {e}");
+            }
+
+            if !timeline.is_empty() && let Err(e) = write_timeline(&zip_path, epoch, &timeline).await {
+                error!("[{zip_guild_id}] {e}");
+            }
+
+            // Make sure every track (and its directory entry) is on disk before anything is built from them.
+            let sync_path = zip_path.clone();
+            let sync_metrics = metrics.clone();
+            let sync_res = tokio::task::spawn_blocking(move || sync_dir(&sync_path, &sync_metrics.sync)).await;
+            match sync_res {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!("[{zip_guild_id}] {e}"),
+                Err(e) => error!("[{zip_guild_id}] Directory sync task failed: {e:?}"),
+            }
+
+            let res = package_recording(zip_path, zip_name, storage_key, zip_guild_id, storage, encryption, &metrics, progress_tx).await;
+
+            if let Err(e) = &res {
+                error!("[{zip_guild_id}] Failed to store recording: {e}");
+            }
+
+            if zip_tx.send(res).is_err() {
+                error!("[{zip_guild_id}] Failed to send zip files result to the channel!");
+            }
         });
 
-        Some(RecordingSummary {
+        RecordingSummary {
+            id: self.metadata.output_dir_name.clone(),
             started: self.metadata.started.clone(),
-            ended: Utc::now(),
+            ended,
+            channel_id: self.metadata.channel_id,
             known_users,
+            silent_users,
             zip_rx,
-        })
+            zip_progress: progress_rx,
+            write_errors: self.write_errors.load(Ordering::Relaxed),
+            first_error: self.first_error.lock().unwrap().take(),
+        }
+    }
+}
+
+/// Writes the checksum manifest, zips the recording, encrypts it if the guild has keys, and hands it to storage.
+#[allow(clippy::too_many_arguments)]
+async fn package_recording(
+    dir: PathBuf,
+    zip_name: String,
+    storage_key: String,
+    guild_id: GuildId,
+    storage: Arc<dyn StorageBackend>,
+    encryption: Option<Arc<EncryptionConfig>>,
+    metrics: &RecorderMetrics,
+    progress_tx: watch::Sender<ZipProgress>,
+) -> Result<StoredRecording, RecorderError> {
+    let manifest_dir = dir.clone();
+    tokio::task::spawn_blocking(move || write_checksums(&manifest_dir)).await
+        .map_err(|source| RecorderError::Task { name: "Checksum", source })??;
+
+    let zip_start = Instant::now();
+    let zip_path = zip_files(dir, zip_name, guild_id, progress_tx).await?;
+    match tokio::fs::metadata(&zip_path).await {
+        Ok(x) => metrics.zip_finished(zip_start.elapsed(), x.len()),
+        Err(e) => warn!("[{guild_id}] Failed to read the size of {}: {e}", zip_path.display()),
+    }
+
+    let digest_path = zip_path.clone();
+    let sha256 = tokio::task::spawn_blocking(move || write_digest_file(&digest_path)).await
+        .map_err(|source| RecorderError::Task { name: "Checksum", source })??;
+    info!("[{guild_id}] Zip SHA-256: {sha256}");
+
+    let recipients = encryption.as_ref().and_then(|x| x.recipients(guild_id)).map(|x| x.to_vec());
+    let location = match recipients {
+        None => storage.store(&zip_path, &storage_key).await?,
+        Some(recipients) => {
+            let delete_plaintext = encryption.as_ref().is_some_and(|x| x.delete_plaintext);
+            let encrypted = encrypt_recording(zip_path, recipients, delete_plaintext, guild_id).await?;
+            storage.store(&encrypted, &format!("{storage_key}.{ENCRYPTED_EXT}")).await?
+        }
+    };
+
+    Ok(StoredRecording {
+        location,
+        sha256,
+    })
+}
+
+/// Encrypts a finished zip, optionally removing it and the tracks it was built from afterwards.
+/// Checksum files are always kept.
+async fn encrypt_recording(zip_path: PathBuf, recipients: Vec<Recipient>, delete_plaintext: bool, guild_id: GuildId) -> Result<PathBuf, RecorderError> {
+    debug!("[{guild_id}] Encrypting {} to {} recipient(s)", zip_path.display(), recipients.len());
+
+    let source = zip_path.clone();
+    let encrypted = tokio::task::spawn_blocking(move || encrypt_file(&source, &recipients)).await
+        .map_err(|source| RecorderError::Task { name: "Encryption", source })??;
+
+    info!("[{guild_id}] Wrote encrypted recording: {}", encrypted.display());
+
+    let digest_path = encrypted.clone();
+    tokio::task::spawn_blocking(move || write_digest_file(&digest_path)).await
+        .map_err(|source| RecorderError::Task { name: "Checksum", source })??;
+
+    if delete_plaintext {
+        let dir = zip_path.parent().map(|x| x.to_path_buf()).unwrap_or_default();
+        let mut entries = tokio::fs::read_dir(&dir).await.map_err(|e| RecorderError::io("read", &dir, e))?;
+
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            // Checksums reveal nothing about the audio and are needed to verify the encrypted copy later.
+            let is_checksum = entry.file_name() == CHECKSUMS_FILE || path.extension().is_some_and(|x| x == DIGEST_EXT);
+            if path == encrypted || is_checksum {
+                continue;
+            }
+
+            match tokio::fs::remove_file(&path).await {
+                Ok(_) => debug!("[{guild_id}] Deleted plaintext {}", path.display()),
+                Err(e) => warn!("[{guild_id}] Failed to delete plaintext {}: {e}", path.display()),
+            }
+        }
+    }
+
+    Ok(encrypted)
+}
+
+/// Tidies up a clip once it has been offered to Discord, returning the file that was kept, if any.
+///
+/// A posted clip is removed along with its directory. Otherwise only the zip is kept, encrypted for guilds with keys.
+pub(crate) async fn settle_clip(zip_path: &Path, posted: bool, encryption: Option<Arc<EncryptionConfig>>, guild_id: GuildId) -> Option<PathBuf> {
+    let dir = zip_path.parent()?.to_path_buf();
+
+    if posted {
+        match tokio::fs::remove_dir_all(&dir).await {
+            Ok(_) => debug!("[{guild_id}] Removed posted clip {}", dir.display()),
+            Err(e) => warn!("[{guild_id}] Failed to remove posted clip {}: {e}", dir.display()),
+        }
+        return None;
+    }
+
+    // The tracks are all in the zip, so they are only left behind by a clip that couldn't be posted.
+    match tokio::fs::read_dir(&dir).await {
+        Ok(mut entries) => {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let path = entry.path();
+                if path == zip_path {
+                    continue;
+                }
+
+                if let Err(e) = tokio::fs::remove_file(&path).await {
+                    warn!("[{guild_id}] Failed to remove clip track {}: {e}", path.display());
+                }
+            }
+        }
+        Err(e) => warn!("[{guild_id}] Failed to read clip directory {}: {e}", dir.display()),
+    }
+
+    let recipients = match encryption.as_ref().and_then(|x| x.recipients(guild_id)) {
+        Some(x) => x.to_vec(),
+        None => return Some(zip_path.to_path_buf()),
+    };
+
+    let delete_plaintext = encryption.is_some_and(|x| x.delete_plaintext);
+    match encrypt_recording(zip_path.to_path_buf(), recipients, delete_plaintext, guild_id).await {
+        Ok(encrypted) => Some(encrypted),
+        Err(e) => {
+            error!("[{guild_id}] Failed to encrypt clip, keeping it in plaintext: {e}");
+            Some(zip_path.to_path_buf())
+        }
     }
 }
 
@@ -116,4 +481,46 @@ impl Drop for CallWriter {
     fn drop(&mut self) {
         trace!("[{}] CallWriter::drop", self.metadata.guild_id);
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn write_clip_dir(root: &Path) -> PathBuf {
+        let dir = root.join("clip");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        tokio::fs::write(dir.join("01_alice_1.opus"), b"audio").await.unwrap();
+        let zip_path = dir.join("clip.zip");
+        tokio::fs::write(&zip_path, b"zip").await.unwrap();
+        zip_path
+    }
+
+    #[tokio::test]
+    async fn settles_clips_by_whether_they_were_posted() {
+        let root = std::env::temp_dir().join(format!("disrecord_clip_test_{}", std::process::id()));
+        let guild_id = GuildId::new(1);
+
+        let zip_path = write_clip_dir(&root.join("posted")).await;
+        assert_eq!(settle_clip(&zip_path, true, None, guild_id).await, None);
+        assert!(!root.join("posted").join("clip").exists());
+
+        let zip_path = write_clip_dir(&root.join("kept")).await;
+        assert_eq!(settle_clip(&zip_path, false, None, guild_id).await, Some(zip_path.clone()));
+        assert!(zip_path.is_file());
+        assert!(!zip_path.with_file_name("01_alice_1.opus").exists());
+
+        let keys_path = root.join("keys.txt");
+        let key = age::x25519::Identity::generate().to_public();
+        tokio::fs::write(&keys_path, format!("{guild_id} {key}\n")).await.unwrap();
+        let encryption = Arc::new(EncryptionConfig::load(&keys_path, true).unwrap());
+
+        let zip_path = write_clip_dir(&root.join("encrypted")).await;
+        let kept = settle_clip(&zip_path, false, Some(encryption), guild_id).await.unwrap();
+        assert_eq!(kept.extension().unwrap(), ENCRYPTED_EXT);
+        assert!(kept.is_file());
+        assert!(!zip_path.exists());
+
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
 }
\ No newline at end of file
@@ -0,0 +1,64 @@
+use std::path::Path;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use crate::recorder::error::RecorderError;
+use crate::recorder::writer::ChatMessage;
+
+pub const CHAT_JSON_FILE: &str = "chat.json";
+pub const CHAT_TEXT_FILE: &str = "chat.txt";
+
+#[derive(Debug, Serialize)]
+struct ChatLogEntry<'a> {
+    id: String,
+    author_id: String,
+    author: &'a str,
+    timestamp: String,
+    /// Milliseconds since the recording started; negative if the message was sent just before.
+    offset_ms: i64,
+    content: &'a str,
+    attachments: &'a [String],
+}
+
+/// Formats an offset from the start of the session as `+hh:mm:ss`.
+fn format_offset(offset_ms: i64) -> String {
+    let sign = if offset_ms < 0 { '-' } else { '+' };
+    let seconds = offset_ms.abs() / 1000;
+    format!("{sign}{:02}:{:02}:{:02}", seconds / 3600, (seconds / 60) % 60, seconds % 60)
+}
+
+/// Writes the chat of a session as `chat.json` and a human-readable `chat.txt`.
+pub async fn write_chat_log(dir: &Path, started: DateTime<Utc>, messages: &[ChatMessage]) -> Result<(), RecorderError> {
+    let mut entries = Vec::with_capacity(messages.len());
+    let mut text = String::new();
+
+    for message in messages {
+        let offset_ms = message.timestamp.signed_duration_since(started).num_milliseconds();
+        let timestamp = message.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true);
+
+        text += &format!("[{timestamp} {}] {} ({}): ", format_offset(offset_ms), message.author, message.author_id);
+        text += &message.content.replace('\n', "\n    ");
+        text.push('\n');
+        for attachment in &message.attachments {
+            text += &format!("    [attachment] {attachment}\n");
+        }
+
+        entries.push(ChatLogEntry {
+            id: message.id.to_string(),
+            author_id: message.author_id.to_string(),
+            author: &message.author,
+            timestamp,
+            offset_ms,
+            content: &message.content,
+            attachments: &message.attachments,
+        });
+    }
+
+    let json_path = dir.join(CHAT_JSON_FILE);
+    let json = serde_json::to_vec_pretty(&entries).map_err(|e| RecorderError::io("serialize", &json_path, e.into()))?;
+    tokio::fs::write(&json_path, json).await.map_err(|e| RecorderError::io("write to", &json_path, e))?;
+
+    let text_path = dir.join(CHAT_TEXT_FILE);
+    tokio::fs::write(&text_path, text).await.map_err(|e| RecorderError::io("write to", &text_path, e))?;
+
+    Ok(())
+}
\ No newline at end of file
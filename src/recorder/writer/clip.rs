@@ -0,0 +1,45 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use rand::Rng;
+use crate::recorder::error::RecorderError;
+use crate::recorder::writer::muxer::ogg_opus::CommentHeader;
+use crate::recorder::writer::muxer::ogg_writer::OggPageWriter;
+use crate::recorder::writer::muxer::opus_toc::OpusToc;
+use crate::recorder::writer::stream_writer::{id_header, silence_frames, ClipPacket};
+
+/// Writes a standalone track holding `packets` between `start` and `end` (in samples since the recording
+/// started), with silence in any gaps so that clips of different users line up.
+pub fn write_clip(path: &Path, comments: Vec<String>, packets: &[ClipPacket], start: u64, end: u64) -> Result<(), RecorderError> {
+    let file = File::create(path).map_err(|e| RecorderError::io("create", path, e))?;
+    let mut writer = OggPageWriter::new(BufWriter::new(file), path.to_path_buf(), rand::rng().random::<u32>());
+
+    let comment_header = CommentHeader {
+        vendor: "disrecord".to_string(),
+        comments,
+    };
+
+    writer.write_header_packet(&id_header().build())?;
+    writer.write_header_packet(&comment_header.build())?;
+
+    let mut position = start;
+    for packet in packets {
+        fill_silence(&mut writer, packet.position.saturating_sub(position))?;
+        writer.push(&packet.opus, packet.samples)?;
+        position = position.max(packet.position) + packet.samples as u64;
+    }
+    fill_silence(&mut writer, end.saturating_sub(position))?;
+
+    let mut file = writer.finish()?;
+    file.flush().map_err(|e| RecorderError::io("write to", path, e))?;
+
+    Ok(())
+}
+
+fn fill_silence<W: Write>(writer: &mut OggPageWriter<W>, samples: u64) -> Result<(), RecorderError> {
+    for frame in silence_frames(samples) {
+        writer.push(frame, OpusToc::from(frame[0]).samples_per_frame())?;
+    }
+
+    Ok(())
+}
\ No newline at end of file
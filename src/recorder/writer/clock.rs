@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use chrono::{DateTime, TimeDelta, Utc};
+use crate::recorder::writer::muxer::opus_toc::GRANULE_SAMPLE_RATE;
+use crate::recorder::writer::stream_writer::elapsed_samples;
+
+/// Where the position on a recording's timeline comes from.
+#[derive(Clone, Debug)]
+pub enum Clock {
+    /// Samples elapsed since the recording started, for live calls.
+    Wall(Instant),
+    /// Moved forward by whatever is feeding the recording, so replays don't depend on how fast they run.
+    Manual(Arc<AtomicU64>),
+}
+
+impl Clock {
+    /// A clock starting at zero that is moved forward by hand.
+    pub fn manual() -> Self {
+        Clock::Manual(Arc::new(AtomicU64::new(0)))
+    }
+
+    /// The current position, in samples since the recording started.
+    pub fn samples(&self) -> u64 {
+        match self {
+            Clock::Wall(epoch) => elapsed_samples(*epoch),
+            Clock::Manual(samples) => samples.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Moves a manual clock forward to `samples`. Wall clocks move on their own, so this does nothing to them.
+    pub fn advance_to(&self, samples: u64) {
+        if let Clock::Manual(x) = self {
+            x.fetch_max(samples, Ordering::Relaxed);
+        }
+    }
+
+    /// The current time, given when the recording started.
+    pub fn now_utc(&self, started: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Clock::Wall(_) => Utc::now(),
+            Clock::Manual(samples) => {
+                let millis = samples.load(Ordering::Relaxed) * 1000 / GRANULE_SAMPLE_RATE as u64;
+                started + TimeDelta::milliseconds(millis as i64)
+            }
+        }
+    }
+}
\ No newline at end of file
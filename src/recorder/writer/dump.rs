@@ -0,0 +1,269 @@
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, ParseError, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildId, UserId};
+use serenity::async_trait;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use crate::recorder::error::RecorderError;
+use crate::recorder::writer::source::{TimedUpdate, VoiceSource};
+use crate::recorder::writer::{OpusUpdate, UserUpdate, VoiceUpdateType};
+use crate::recorder::RecordingMetadata;
+
+/// Extension of packet dump files.
+pub const DUMP_EXT: &str = "dump";
+
+const MAGIC: &[u8; 8] = b"DRDUMP1\n";
+const RECORD_USER: u8 = 0;
+const RECORD_OPUS: u8 = 1;
+/// Length written in place of a missing name.
+const NO_STRING: u32 = u32::MAX;
+/// Most packets a voice tick can hold, one per speaker. Counts and lengths are checked against these before
+/// anything is allocated, so that a damaged dump ends the replay instead of exhausting memory.
+const MAX_TICK_PACKETS: u32 = 4096;
+/// Largest Opus packet: 48 frames of at most 1275 bytes each (RFC 6716 §3.2.5).
+const MAX_PAYLOAD_LEN: u32 = 1275 * 48;
+/// Longest name that is read back; Discord's are far shorter.
+const MAX_STRING_LEN: u32 = 4096;
+
+/// Describes the call a packet dump was captured from. Written as a line of JSON after the magic.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DumpHeader {
+    /// The recorded guild.
+    pub guild_id: GuildId,
+    /// Name of the guild, if known.
+    pub guild_name: Option<String>,
+    /// The recorded voice channel.
+    pub channel_id: ChannelId,
+    /// Name of the channel, if known.
+    pub channel_name: Option<String>,
+    /// Session ID of the recording the dump was captured from.
+    pub session_id: String,
+    /// When the recording started, in RFC 3339.
+    pub started: String,
+}
+
+impl DumpHeader {
+    /// Parses `started`.
+    pub fn started(&self) -> Result<DateTime<Utc>, ParseError> {
+        DateTime::parse_from_rfc3339(&self.started).map(|x| x.to_utc())
+    }
+}
+
+/// Captures the speaking and packet events of a call so that it can be replayed into the writer later.
+///
+/// After the header, each record is a tag byte and its position in samples, followed by either a user
+/// (ID, SSRC, username, display name) or the packets of one voice tick (user, RTP timestamp, sequence, payload).
+/// Numbers are little-endian, strings and payloads are prefixed with their length. Voice state changes and chat
+/// messages aren't captured, so a replayed recording has no timeline or chat log.
+#[derive(Debug)]
+pub struct DumpWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+}
+
+impl DumpWriter {
+    pub async fn create(path: PathBuf, metadata: &RecordingMetadata) -> Result<Self, RecorderError> {
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await.map_err(|e| RecorderError::io("create directory", dir, e))?;
+        }
+
+        let file = File::create(&path).await.map_err(|e| RecorderError::io("create", &path, e))?;
+
+        let header = DumpHeader {
+            guild_id: metadata.guild_id,
+            guild_name: metadata.guild_name.clone(),
+            channel_id: metadata.channel_id,
+            channel_name: metadata.channel_name.clone(),
+            session_id: metadata.session_id.clone(),
+            started: metadata.started.to_rfc3339_opts(SecondsFormat::Micros, true),
+        };
+
+        let mut data = MAGIC.to_vec();
+        serde_json::to_writer(&mut data, &header).map_err(|e| RecorderError::Dump { path: path.clone(), problem: format!("failed to serialize header: {e}") })?;
+        data.push(b'\n');
+
+        let mut writer = Self {
+            path,
+            file: BufWriter::new(file),
+        };
+        writer.write(&data).await?;
+
+        Ok(writer)
+    }
+
+    /// Appends an update. Only users and voice ticks are kept: voice state changes and chat messages are dropped,
+    /// so a replay produces audio without a timeline or chat log.
+    pub async fn push(&mut self, update: &TimedUpdate) -> Result<(), RecorderError> {
+        let mut data = Vec::new();
+
+        match &update.update {
+            VoiceUpdateType::User(user) => {
+                data.push(RECORD_USER);
+                data.extend_from_slice(&update.at.to_le_bytes());
+                data.extend_from_slice(&user.user.get().to_le_bytes());
+                data.extend_from_slice(&user.ssrc.to_le_bytes());
+                put_string(&mut data, user.username.as_deref());
+                put_string(&mut data, user.display_name.as_deref());
+            }
+            VoiceUpdateType::Opus(packets) => {
+                data.push(RECORD_OPUS);
+                data.extend_from_slice(&update.at.to_le_bytes());
+                data.extend_from_slice(&(packets.len() as u32).to_le_bytes());
+                for packet in packets {
+                    data.extend_from_slice(&packet.user.get().to_le_bytes());
+                    data.extend_from_slice(&packet.timestamp.to_le_bytes());
+                    data.extend_from_slice(&packet.sequence.to_le_bytes());
+                    data.extend_from_slice(&(packet.opus_data.len() as u32).to_le_bytes());
+                    data.extend_from_slice(&packet.opus_data);
+                }
+            }
+            // Not part of the dump format; replays have no timeline or chat log.
+            VoiceUpdateType::Chat(_) | VoiceUpdateType::VoiceState(_) => return Ok(()),
+        }
+
+        self.write(&data).await
+    }
+
+    pub async fn finish(mut self) -> Result<PathBuf, RecorderError> {
+        self.file.flush().await.map_err(|e| RecorderError::io("write to", &self.path, e))?;
+        Ok(self.path)
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), RecorderError> {
+        self.file.write_all(data).await.map_err(|e| RecorderError::io("write to", &self.path, e))
+    }
+}
+
+fn put_string(data: &mut Vec<u8>, value: Option<&str>) {
+    match value {
+        Some(x) => {
+            data.extend_from_slice(&(x.len() as u32).to_le_bytes());
+            data.extend_from_slice(x.as_bytes());
+        }
+        None => data.extend_from_slice(&NO_STRING.to_le_bytes()),
+    }
+}
+
+/// Replays a packet dump written by `DumpWriter`.
+#[derive(Debug)]
+pub struct DumpSource {
+    path: PathBuf,
+    file: BufReader<File>,
+}
+
+impl DumpSource {
+    /// Opens a dump, reading and checking its header.
+    pub async fn open(path: &Path) -> Result<(Self, DumpHeader), RecorderError> {
+        let file = File::open(path).await.map_err(|e| RecorderError::io("open", path, e))?;
+        let mut file = BufReader::new(file);
+
+        let mut magic = [0; MAGIC.len()];
+        file.read_exact(&mut magic).await.map_err(|e| RecorderError::io("read", path, e))?;
+        if &magic != MAGIC {
+            return Err(RecorderError::Dump { path: path.to_path_buf(), problem: "not a packet dump".to_string() });
+        }
+
+        let mut header = String::new();
+        file.read_line(&mut header).await.map_err(|e| RecorderError::io("read", path, e))?;
+        let header = serde_json::from_str(&header).map_err(|e| RecorderError::Dump { path: path.to_path_buf(), problem: format!("invalid header: {e}") })?;
+
+        let source = Self {
+            path: path.to_path_buf(),
+            file,
+        };
+
+        Ok((source, header))
+    }
+
+    /// Reads the next record, or `None` at the end of the file.
+    async fn read_record(&mut self) -> Result<Option<TimedUpdate>, RecorderError> {
+        let tag = match self.file.read_u8().await {
+            Ok(x) => x,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(RecorderError::io("read", &self.path, e)),
+        };
+
+        let at = self.read_u64().await?;
+
+        let update = match tag {
+            RECORD_USER => VoiceUpdateType::User(UserUpdate {
+                user: UserId::new(self.read_u64().await?),
+                ssrc: self.read_u32().await?,
+                username: self.read_string().await?,
+                display_name: self.read_string().await?,
+            }),
+            RECORD_OPUS => {
+                let count = self.read_u32().await?;
+                if count > MAX_TICK_PACKETS {
+                    return Err(self.damaged(format!("voice tick with {count} packets")));
+                }
+
+                let mut packets = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let user = UserId::new(self.read_u64().await?);
+                    let timestamp = self.read_u32().await?;
+                    let sequence = self.file.read_u16_le().await.map_err(|e| RecorderError::io("read", &self.path, e))?;
+                    let opus_data = self.read_bytes().await?;
+                    packets.push(OpusUpdate { user, opus_data, timestamp, sequence });
+                }
+                VoiceUpdateType::Opus(packets)
+            }
+            x => return Err(self.damaged(format!("unknown record type {x}"))),
+        };
+
+        Ok(Some(TimedUpdate { at, update }))
+    }
+
+    async fn read_u32(&mut self) -> Result<u32, RecorderError> {
+        self.file.read_u32_le().await.map_err(|e| RecorderError::io("read", &self.path, e))
+    }
+
+    async fn read_u64(&mut self) -> Result<u64, RecorderError> {
+        self.file.read_u64_le().await.map_err(|e| RecorderError::io("read", &self.path, e))
+    }
+
+    async fn read_bytes(&mut self) -> Result<Vec<u8>, RecorderError> {
+        let len = self.read_u32().await?;
+        if len > MAX_PAYLOAD_LEN {
+            return Err(self.damaged(format!("packet of {len} bytes")));
+        }
+
+        let mut data = vec![0; len as usize];
+        self.file.read_exact(&mut data).await.map_err(|e| RecorderError::io("read", &self.path, e))?;
+        Ok(data)
+    }
+
+    async fn read_string(&mut self) -> Result<Option<String>, RecorderError> {
+        let len = self.read_u32().await?;
+        if len == NO_STRING {
+            return Ok(None);
+        }
+        if len > MAX_STRING_LEN {
+            return Err(self.damaged(format!("name of {len} bytes")));
+        }
+
+        let mut data = vec![0; len as usize];
+        self.file.read_exact(&mut data).await.map_err(|e| RecorderError::io("read", &self.path, e))?;
+        String::from_utf8(data).map(Some).map_err(|e| self.damaged(format!("invalid name: {e}")))
+    }
+
+    /// A `Dump` error for this file.
+    fn damaged(&self, problem: String) -> RecorderError {
+        RecorderError::Dump { path: self.path.clone(), problem }
+    }
+}
+
+#[async_trait]
+impl VoiceSource for DumpSource {
+    async fn next(&mut self) -> Option<TimedUpdate> {
+        match self.read_record().await {
+            Ok(x) => x,
+            Err(e) => {
+                // A capture cut short by a crash ends mid-record, so treat this as the end of the dump.
+                warn!("Stopping replay of {}: {e}", self.path.display());
+                None
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,79 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use crate::recorder::RecordingMetadata;
+use crate::recorder::error::RecorderError;
+use crate::recorder::writer::muxer::opus_toc::GRANULE_SAMPLE_RATE;
+use crate::recorder::writer::stream_writer::StreamWriter;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Describes every file in a recording and where it sits on the session timeline.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    session_id: String,
+    guild_id: String,
+    guild_name: Option<String>,
+    channel_id: String,
+    channel_name: Option<String>,
+    started: String,
+    ended: String,
+    sample_rate: usize,
+    tracks: Vec<ManifestTrack>,
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestTrack {
+    user_id: String,
+    username: Option<String>,
+    display_name: Option<String>,
+    ssrc: u32,
+    segments: Vec<ManifestSegment>,
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestSegment {
+    file: String,
+    /// Position of the segment's first sample on the session timeline, at `sample_rate`.
+    granule_offset: u64,
+    samples: u64,
+}
+
+impl Manifest {
+    pub fn new(metadata: &RecordingMetadata, ended: DateTime<Utc>, streams: &[Arc<StreamWriter>]) -> Self {
+        let tracks = streams.iter().map(|stream| {
+            ManifestTrack {
+                user_id: stream.user_id().to_string(),
+                username: stream.username().map(|x| x.to_string()),
+                display_name: stream.display_name().map(|x| x.to_string()),
+                ssrc: stream.ssrc(),
+                segments: stream.segments().into_iter().map(|segment| ManifestSegment {
+                    file: segment.path.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default(),
+                    granule_offset: segment.offset,
+                    samples: segment.samples,
+                }).collect(),
+            }
+        }).collect();
+
+        Self {
+            session_id: metadata.session_id.clone(),
+            guild_id: metadata.guild_id.to_string(),
+            guild_name: metadata.guild_name.clone(),
+            channel_id: metadata.channel_id.to_string(),
+            channel_name: metadata.channel_name.clone(),
+            started: metadata.started.to_rfc3339_opts(SecondsFormat::Secs, true),
+            ended: ended.to_rfc3339_opts(SecondsFormat::Secs, true),
+            sample_rate: GRANULE_SAMPLE_RATE,
+            tracks,
+        }
+    }
+
+    pub async fn write(&self, dir: &Path) -> Result<PathBuf, RecorderError> {
+        let path = dir.join(MANIFEST_FILE);
+        let json = serde_json::to_vec_pretty(self).map_err(|e| RecorderError::io("serialize", &path, e.into()))?;
+        tokio::fs::write(&path, json).await.map_err(|e| RecorderError::io("write to", &path, e))?;
+
+        Ok(path)
+    }
+}
\ No newline at end of file
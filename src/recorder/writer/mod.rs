@@ -1,95 +1,446 @@
-mod call_writer;
-mod stream_writer;
-mod muxer;
+/// Writing a single call.
+pub mod call_writer;
+mod chat_log;
+mod clip;
+pub(crate) mod dump;
+mod manifest;
+pub(crate) mod stream_writer;
+/// Positions on a recording's timeline.
+pub mod clock;
+/// Reading and writing Ogg Opus.
+pub mod muxer;
+/// Where a call's updates come from.
+pub mod source;
+/// Voice state changes during a recording.
+pub mod timeline;
+mod repair;
 mod zipper;
 
-use crate::recorder::writer::call_writer::CallWriter;
+pub use crate::recorder::writer::dump::{DumpHeader, DumpSource, DUMP_EXT};
+pub use crate::recorder::writer::stream_writer::{Segment, StreamWriter};
+
+use crate::recorder::writer::call_writer::{settle_clip, CallWriter};
+use crate::recorder::writer::clock::Clock;
+use crate::recorder::writer::dump::DumpWriter;
+use crate::recorder::writer::source::{QueueSource, TimedUpdate, VoiceSource};
+use crate::recorder::writer::timeline::VoiceEvent;
 use crate::recorder::{RecorderConfig, RecordingMetadata, RecordingSummary};
-use chrono::Utc;
+use crate::recorder::metrics;
+use crate::recorder::encryption::{encrypt_file, EncryptionConfig};
+use crate::recorder::metrics::RecorderMetrics;
+use crate::recorder::error::{RecorderError, StateError};
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
-use serenity::all::{GuildId, UserId};
-use std::sync::Arc;
+use rand::Rng;
+use serenity::all::{ChannelId, GuildId, MessageId, UserId};
+use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 
+/// A single voice packet received from a user.
 #[derive(Debug, PartialEq)]
 pub struct OpusUpdate {
+    /// The user who sent the packet.
     pub user: UserId,
+    /// The Opus packet.
     pub opus_data: Vec<u8>,
+    /// RTP timestamp of the packet, in 48 kHz samples.
+    pub timestamp: u32,
+    /// RTP sequence number of the packet.
+    pub sequence: u16,
 }
 
+/// Ties an SSRC to a user, sent when they start speaking.
 #[derive(Debug, PartialEq)]
 pub struct UserUpdate {
+    /// The user.
     pub user: UserId,
+    /// The user's Discord username, if known.
     pub username: Option<String>,
+    /// The user's display name in the guild, if known.
+    pub display_name: Option<String>,
+    /// The SSRC their packets are received on.
+    pub ssrc: u32,
+}
+
+/// A text message posted in the recorded voice channel's chat.
+#[derive(Debug, PartialEq)]
+pub struct ChatMessage {
+    /// ID of the message.
+    pub id: MessageId,
+    /// ID of the author.
+    pub author_id: UserId,
+    /// Name of the author.
+    pub author: String,
+    /// When the message was posted.
+    pub timestamp: DateTime<Utc>,
+    /// Text of the message.
+    pub content: String,
+    /// URLs of the message's attachments.
+    pub attachments: Vec<String>,
 }
 
+/// Something that happened in a recorded call.
 #[derive(Debug, PartialEq)]
 pub enum VoiceUpdateType {
+    /// The packets received during one voice tick.
     Opus(Vec<OpusUpdate>),
+    /// A user started speaking.
     User(UserUpdate),
+    /// A chat message was posted.
+    Chat(ChatMessage),
+    /// A user's voice state changed.
+    VoiceState(VoiceEvent),
 }
 
+/// An update for a guild's recording.
 #[derive(Debug, PartialEq)]
 pub struct VoiceUpdate {
+    /// The recorded guild.
     pub guild: GuildId,
+    /// What happened.
     pub update: VoiceUpdateType,
 }
 
+/// A running call, with the queue feeding its writer task.
+#[derive(Debug)]
+struct CallHandle {
+    writer: Arc<CallWriter>,
+    /// Queue of a live call; replays are fed from their own source instead.
+    tx: Option<mpsc::UnboundedSender<TimedUpdate>>,
+    task: JoinHandle<()>,
+    /// Voice ticks waiting in the queue. Only these are limited, as every other update is needed to make sense of them.
+    queued_ticks: Arc<AtomicUsize>,
+    /// Voice ticks dropped because the writer task had fallen behind.
+    dropped: AtomicU64,
+    /// When the writer task started on the update it is working on, if any.
+    busy_since: Arc<Mutex<Option<Instant>>>,
+}
+
+/// How a running call's writer task is doing.
+#[derive(Clone, Debug)]
+pub struct CallHealth {
+    /// The recorded guild.
+    pub guild_id: GuildId,
+    /// False if the task has exited, e.g. by panicking, while the call is still running.
+    pub task_alive: bool,
+    /// How long the task has been working on its current update, if it isn't waiting for one.
+    pub busy_for: Option<Duration>,
+}
+
+/// Runs a `CallWriter` for every guild being recorded, each fed by its own task.
 #[derive(Debug)]
 pub struct Writer {
     config: RecorderConfig,
-    calls: DashMap<GuildId, Arc<CallWriter>>,
+    calls: DashMap<GuildId, CallHandle>,
+    metrics: Arc<RecorderMetrics>,
 }
 
 impl Writer {
+    /// Creates a writer with no calls running.
     pub fn new(config: RecorderConfig) -> Self {
         Self {
             config,
             calls: DashMap::new(),
+            metrics: Arc::new(RecorderMetrics::default()),
         }
     }
 
-    pub fn start(&self, guild_id: GuildId) {
+    /// Starts recording a call in the guild, creating its directory under `base_dir` and spawning its writer task.
+    ///
+    /// Updates for the guild can be sent once this returns.
+    pub fn start(&self, guild_id: GuildId, guild_name: Option<String>, channel_id: ChannelId, channel_name: Option<String>) {
         let started = Utc::now();
+        let epoch = Instant::now();
+        let session_id = format!("{:016x}", rand::rng().random::<u64>());
+        let output_dir_name = started.format(self.config.subdir_fmt.as_str()).to_string();
+        let output_dir = self.config.base_dir.join(format!("{}", guild_id)).join(output_dir_name.as_str());
+
+        let rec_metadata = RecordingMetadata {
+            guild_id,
+            guild_name,
+            channel_id,
+            channel_name,
+            session_id,
+            output_dir,
+            output_dir_name,
+            started,
+            epoch,
+            clock: Clock::Wall(epoch),
+        };
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let queued_ticks = Arc::new(AtomicUsize::new(0));
+        self.start_session(rec_metadata, Some(tx), queued_ticks.clone(), Box::new(QueueSource::new(rx, queued_ticks)));
+    }
+
+    /// Starts writing a recording from the packets captured in a dump, as fast as they can be read.
+    /// The result only depends on the dump, so replaying the same dump twice gives identical tracks.
+    pub async fn replay(&self, dump_path: &Path) -> Result<RecordingSummary, RecorderError> {
+        let (source, header) = DumpSource::open(dump_path).await?;
+
+        let guild_id = header.guild_id;
+        let started = header.started().map_err(|e| RecorderError::Dump { path: dump_path.to_path_buf(), problem: format!("invalid start time: {e}") })?;
         let output_dir_name = started.format(self.config.subdir_fmt.as_str()).to_string();
         let output_dir = self.config.base_dir.join(format!("{}", guild_id)).join(output_dir_name.as_str());
 
+        info!("[{guild_id}] Replaying session {} from {}", header.session_id, dump_path.display());
+
         let rec_metadata = RecordingMetadata {
             guild_id,
+            guild_name: header.guild_name,
+            channel_id: header.channel_id,
+            channel_name: header.channel_name,
+            session_id: header.session_id,
             output_dir,
             output_dir_name,
             started,
+            epoch: Instant::now(),
+            clock: Clock::manual(),
+        };
+
+        self.start_session(rec_metadata, None, Arc::new(AtomicUsize::new(0)), Box::new(source));
+        self.finish(guild_id).await
+    }
+
+    fn start_session(&self, metadata: RecordingMetadata, tx: Option<mpsc::UnboundedSender<TimedUpdate>>, queued_ticks: Arc<AtomicUsize>, mut source: Box<dyn VoiceSource>) {
+        let guild_id = metadata.guild_id;
+        let capture_path = self.config.capture_packets.then(|| {
+            self.config.base_dir.join(guild_id.to_string()).join("captures").join(format!("{}.{DUMP_EXT}", metadata.output_dir_name))
+        });
+
+        let writer = Arc::new(CallWriter::new(metadata, &self.config, self.metrics.clone()));
+        let encryption = self.config.encryption.clone();
+
+        let busy_since = Arc::new(Mutex::new(None));
+
+        // Each call gets its own task, so one guild's slow storage can't hold up any other.
+        let task_writer = writer.clone();
+        let task_busy_since = busy_since.clone();
+        let task = tokio::spawn(async move {
+            let mut capture = match capture_path {
+                Some(path) => match DumpWriter::create(path, task_writer.metadata()).await {
+                    Ok(x) => Some(x),
+                    Err(e) => {
+                        error!("[{guild_id}] Failed to start packet capture: {e}");
+                        None
+                    }
+                },
+                None => None,
+            };
+
+            while let Some(update) = source.next().await {
+                *task_busy_since.lock().unwrap() = Some(Instant::now());
+                task_writer.metadata().clock.advance_to(update.at);
+
+                if let Some(dump) = &mut capture && let Err(e) = dump.push(&update).await {
+                    error!("[{guild_id}] Stopping packet capture: {e}");
+                    capture = None;
+                }
+
+                if let Err(e) = task_writer.push(update.at, update.update).await {
+                    task_writer.record_error(e);
+                }
+
+                *task_busy_since.lock().unwrap() = None;
+            }
+
+            if let Some(dump) = capture {
+                match dump.finish().await {
+                    Ok(path) => {
+                        info!("[{guild_id}] Saved packet capture to {}", path.display());
+                        seal_capture(path, encryption, guild_id).await;
+                    }
+                    Err(e) => error!("[{guild_id}] Failed to finish packet capture: {e}"),
+                }
+            }
+        });
+
+        let handle = CallHandle {
+            writer,
+            tx,
+            task,
+            queued_ticks,
+            dropped: AtomicU64::new(0),
+            busy_since,
+        };
+
+        if self.calls.insert(guild_id, handle).is_some() {
+            warn!("[{guild_id}] Replaced a recording that was never finished!");
+        }
+    }
+
+    /// Queues an update for the call's writer task without waiting.
+    /// Voice ticks are dropped while `queue_depth` of them are already waiting; every other update is always queued.
+    pub fn send(&self, update: VoiceUpdate) {
+        self.queue(update, true);
+    }
+
+    /// Queues an update like `send`, but never drops it, e.g. for the last packets flushed out of a call.
+    pub fn send_without_dropping(&self, update: VoiceUpdate) {
+        self.queue(update, false);
+    }
+
+    fn queue(&self, update: VoiceUpdate, may_drop: bool) {
+        let guild_id = update.guild;
+
+        let call = match self.calls.get(&guild_id) {
+            Some(x) => x,
+            None => {
+                error!("Received voice data for guild without call: {guild_id}");
+                return;
+            }
+        };
+
+        let tx = match &call.tx {
+            Some(x) => x,
+            None => {
+                warn!("[{guild_id}] Ignoring live voice data while replaying");
+                return;
+            }
+        };
+
+        if let VoiceUpdateType::Opus(_) = update.update {
+            let queued = call.queued_ticks.fetch_add(1, Ordering::Relaxed);
+            if may_drop && queued >= self.config.queue_depth {
+                call.queued_ticks.fetch_sub(1, Ordering::Relaxed);
+
+                let dropped = call.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                self.metrics.dropped_tick(guild_id);
+                if dropped <= 10 || dropped.is_multiple_of(1000) {
+                    warn!("[{guild_id}] Writer is falling behind, dropped a voice tick ({dropped} so far)");
+                }
+                return;
+            }
+        }
+
+        let update = TimedUpdate {
+            at: call.writer.metadata().clock.samples(),
+            update: update.update,
         };
 
-        self.calls.insert(guild_id, Arc::new(CallWriter::new(rec_metadata)));
+        if tx.send(update).is_err() {
+            error!("[{guild_id}] Writer task has stopped, dropping update!");
+        }
+    }
+
+    /// Guilds with a recording running.
+    pub fn guilds(&self) -> Vec<GuildId> {
+        self.calls.iter().map(|x| *x.key()).collect()
+    }
+
+    /// How every running call's writer task is doing.
+    pub fn health(&self) -> Vec<CallHealth> {
+        self.calls.iter()
+            .map(|x| CallHealth {
+                guild_id: *x.key(),
+                // Replays end on their own once the dump runs out.
+                task_alive: x.tx.is_none() || !x.task.is_finished(),
+                busy_for: x.busy_since.lock().unwrap().map(|x| x.elapsed()),
+            })
+            .collect()
+    }
+
+    /// Checks that new recordings can be written by creating and removing a file in `base_dir`.
+    pub async fn check_base_dir(&self) -> Result<(), RecorderError> {
+        let dir = &self.config.base_dir;
+        tokio::fs::create_dir_all(dir).await.map_err(|e| RecorderError::io("create directory", dir, e))?;
+
+        let probe = dir.join(format!(".write_check_{:016x}", rand::rng().random::<u64>()));
+        tokio::fs::write(&probe, b"").await.map_err(|e| RecorderError::io("write to", &probe, e))?;
+        tokio::fs::remove_file(&probe).await.map_err(|e| RecorderError::io("remove", &probe, e))
+    }
+
+    /// Writes the state of every running call and the writers' counters in the Prometheus text format.
+    pub fn write_metrics(&self, out: &mut String) {
+        let calls = self.calls.iter()
+            .map(|x| (x.key().to_string(), x.queued_ticks.load(Ordering::Relaxed)))
+            .collect::<Vec<_>>();
+
+        metrics::write_header(out, "disrecord_active_recordings", "gauge", "Recordings running in each guild.");
+        for (guild, _) in &calls {
+            metrics::write_sample(out, "disrecord_active_recordings", &[("guild", guild)], 1);
+        }
+
+        metrics::write_header(out, "disrecord_voice_queue_depth", "gauge", "Voice ticks waiting for each call's writer.");
+        for (guild, depth) in &calls {
+            metrics::write_sample(out, "disrecord_voice_queue_depth", &[("guild", guild)], depth);
+        }
+
+        metrics::write_header(out, "disrecord_voice_queue_capacity", "gauge", "Voice ticks that can wait for a call's writer before new ones are dropped.");
+        metrics::write_sample(out, "disrecord_voice_queue_capacity", &[], self.config.queue_depth);
+
+        self.metrics.write(out);
     }
 
-    pub async fn finish(&self, guild_id: GuildId) -> Option<RecordingSummary> {
+    /// Writes the last `duration` of the guild's recording to a zip and returns its path.
+    pub async fn clip(&self, guild_id: GuildId, duration: Duration) -> Result<PathBuf, RecorderError> {
+        let call = self.calls.get(&guild_id).map(|x| x.writer.clone()).ok_or(StateError::NotRecording)?;
+        call.clip(duration).await
+    }
+
+    /// Removes a clip that was posted, or encrypts one that wasn't for guilds with keys. Returns the file that was kept.
+    pub async fn settle_clip(&self, guild_id: GuildId, zip_path: &Path, posted: bool) -> Option<PathBuf> {
+        settle_clip(zip_path, posted, self.config.encryption.clone(), guild_id).await
+    }
+
+    /// Stops the guild's writer task once its queue is drained and finishes the recording.
+    ///
+    /// Fails with `StateError::NotRecording` if the guild has no recording running.
+    pub async fn finish(&self, guild_id: GuildId) -> Result<RecordingSummary, RecorderError> {
         let call = self.calls.remove(&guild_id);
         match call {
             None => {
                 error!("Tried to finish non-existent recording for guild: {guild_id}");
-                None
+                Err(StateError::NotRecording.into())
             }
             Some((_, call)) => {
-                call.finish().await
+                // Closing the queue lets the task write out whatever is still waiting, then stop.
+                drop(call.tx);
+                if let Err(e) = call.task.await {
+                    error!("[{guild_id}] Writer task failed: {e:?}");
+                }
+
+                let dropped = call.dropped.load(Ordering::Relaxed);
+                if dropped > 0 {
+                    warn!("[{guild_id}] Dropped {dropped} voice tick(s) while the writer was behind");
+                }
+
+                Ok(call.writer.finish().await)
             }
         }
     }
+}
 
-    pub fn run(writer: Arc<Self>, mut voice_rx: mpsc::Receiver<VoiceUpdate>) {
-        tokio::spawn(async move {
-            while let Some(voice_update) = voice_rx.recv().await {
-                match writer.calls.get(&voice_update.guild) {
-                    Some(call) => {
-                        call.push(voice_update.update).await;
-                    }
-                    None => {
-                        error!("Received voice data for guild without call: {}", voice_update.guild);
-                    }
-                }
-            }
-        });
+/// Encrypts a finished packet capture for guilds with keys, since it holds the same audio as the recording,
+/// and removes the plaintext copy if the recording's plaintext is removed too.
+async fn seal_capture(path: PathBuf, encryption: Option<Arc<EncryptionConfig>>, guild_id: GuildId) {
+    let recipients = match encryption.as_ref().and_then(|x| x.recipients(guild_id)) {
+        Some(x) => x.to_vec(),
+        None => return,
+    };
+
+    let source = path.clone();
+    match tokio::task::spawn_blocking(move || encrypt_file(&source, &recipients)).await {
+        Ok(Ok(encrypted)) => info!("[{guild_id}] Encrypted packet capture to {}", encrypted.display()),
+        Ok(Err(e)) => {
+            error!("[{guild_id}] Failed to encrypt packet capture, keeping it in plaintext: {e}");
+            return;
+        }
+        Err(e) => {
+            error!("[{guild_id}] Packet capture encryption task failed, keeping it in plaintext: {e:?}");
+            return;
+        }
+    }
+
+    if encryption.is_some_and(|x| x.delete_plaintext) {
+        match tokio::fs::remove_file(&path).await {
+            Ok(_) => debug!("[{guild_id}] Deleted plaintext {}", path.display()),
+            Err(e) => warn!("[{guild_id}] Failed to delete plaintext {}: {e}", path.display()),
+        }
     }
 }
 
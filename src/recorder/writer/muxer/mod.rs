@@ -1,4 +1,13 @@
+/// Parsing the TOC byte of Opus packets.
 pub mod opus_toc;
+/// Splitting Opus packets into frames.
+pub mod opus_packet;
 mod crc;
+/// Building Ogg pages.
 pub mod ogg;
+/// Reading Ogg pages.
+pub mod ogg_reader;
+/// Writing packets to an Ogg stream.
+pub mod ogg_writer;
+/// The Ogg Opus header packets.
 pub mod ogg_opus;
\ No newline at end of file
@@ -7,13 +7,15 @@ const MAX_PAYLOAD_PER_PAGE: usize = 65_025;
 pub const MAX_SEGMENTS_PER_FRAME: usize = 255;
 const MAX_SEGMENT_SIZE: u16 = 255;
 
-#[derive(Debug)]
+/// The segment table of an Ogg page.
+#[derive(Debug, Default)]
 pub struct OggSegments {
     lacings: Vec<u8>,
     total_size: u16,
 }
 
 impl OggSegments {
+    /// An empty segment table.
     pub fn new() -> Self {
         Self {
             lacings: Vec::new(),
@@ -21,6 +23,7 @@ impl OggSegments {
         }
     }
 
+    /// Empties the table for the next page.
     pub fn clear(&mut self) {
         self.lacings.clear();
         self.total_size = 0;
@@ -73,6 +76,7 @@ impl OggSegments {
     }
 }
 
+/// The header of an Ogg page.
 #[derive(Debug)]
 pub struct OggHeader {
     /// True if page contains the continuation of a packet from the previous page.
@@ -81,13 +85,16 @@ pub struct OggHeader {
     pub begin_stream: bool,
     /// True if the last page of a stream.
     pub end_stream: bool,
+    /// Position of the page's last completed packet, in samples.
     pub granule: u64,
     /// Random number per stream.
     pub serial: u32,
+    /// Page number within the stream.
     pub sequence: u32,
 }
 
 impl OggHeader {
+    /// Builds the page with `segments` and `payload`, or `None` if they don't fit on one.
     pub fn build_page(&self, segments: &OggSegments, payload: &[u8]) -> Option<Vec<u8>> {
         let mut buffer= Vec::new();
 
@@ -1,16 +1,26 @@
+/// Pre-skip written to the ID header, in 48 kHz samples.
 pub const PRESKIP_DEFAULT: u16 = 3840;
 
+/// How the coded streams map to output channels (RFC 7845 §5.1.1).
 pub struct ChannelMappingTable {
+    /// Number of streams in each packet.
     pub stream_count: u8,
+    /// Number of those that are stereo.
     pub coupled_count: u8,
+    /// Output channel of each stream.
     pub channel_mapping: Vec<u8>,
 }
 
+/// Channel mapping family of the ID header.
 #[allow(dead_code)]
 pub enum MappingFamily {
+    /// Mono or stereo, with no mapping table.
     Rtp,
+    /// Vorbis channel order.
     Vorbis(ChannelMappingTable),
+    /// Channels with no defined meaning.
     Unidentified(ChannelMappingTable),
+    /// Reserved families.
     Undefined(ChannelMappingTable),
 }
 
@@ -25,15 +35,22 @@ impl MappingFamily {
     }
 }
 
+/// The `OpusHead` packet that begins an Ogg Opus stream.
 pub struct IdHeader {
+    /// Number of output channels.
     pub channel_count: u8,
+    /// Samples to discard from the start of the decoded audio.
     pub preskip: u16,
+    /// Sample rate of the original audio, for information only.
     pub input_sample_rate: u32,
+    /// Output gain, in Q7.8 dB.
     pub gain: u16,
+    /// How channels are mapped.
     pub mapping_family: MappingFamily,
 }
 
 impl IdHeader {
+    /// Encodes the packet.
     pub fn build(&self) -> Vec<u8> {
         let mut header = Vec::new();
 
@@ -62,12 +79,16 @@ impl IdHeader {
     }
 }
 
+/// The `OpusTags` packet that follows the ID header.
 pub struct CommentHeader {
+    /// Name of the encoder.
     pub vendor: String,
+    /// Tags in `KEY=value` form.
     pub comments: Vec<String>,
 }
 
 impl CommentHeader {
+    /// Encodes the packet.
     pub fn build(&self) -> Vec<u8> {
         let mut header = Vec::new();
 
@@ -76,7 +97,7 @@ impl CommentHeader {
         header.extend_from_slice((self.vendor.len() as u32).to_le_bytes().as_slice());
         header.extend_from_slice(self.vendor.as_bytes());
         
-        header.extend_from_slice(self.comments.len().to_le_bytes().as_slice());
+        header.extend_from_slice((self.comments.len() as u32).to_le_bytes().as_slice());
 
         for comment in &self.comments {
             header.extend_from_slice((comment.len() as u32).to_le_bytes().as_slice());
@@ -0,0 +1,101 @@
+use std::io::{Error, ErrorKind, Read};
+use crate::recorder::writer::muxer::crc::vorbis_crc32;
+use crate::recorder::writer::muxer::ogg::OggHeader;
+
+const HEADER_SIZE: usize = 27;
+
+/// A page read from an Ogg stream.
+#[derive(Debug)]
+pub struct OggPage {
+    /// The page's header.
+    pub header: OggHeader,
+    /// Packets that end on this page, including any continued from previous pages.
+    pub packets: Vec<Vec<u8>>,
+}
+
+/// Reads Ogg pages back out of a stream, reassembling packets that span page boundaries.
+#[derive(Debug)]
+pub struct OggReader<R: Read> {
+    reader: R,
+    partial: Vec<u8>,
+}
+
+impl<R: Read> OggReader<R> {
+    /// Reads pages from `reader`.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Returns the next page, or `None` at a clean end of the stream.
+    pub fn read_page(&mut self) -> Result<Option<OggPage>, Error> {
+        let mut header = [0u8; HEADER_SIZE];
+        match self.reader.read_exact(&mut header) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        if &header[0..4] != b"OggS" {
+            return Err(Error::new(ErrorKind::InvalidData, "Missing Ogg capture pattern"));
+        }
+        if header[4] != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Unsupported Ogg version: {}", header[4])));
+        }
+
+        let header_type = header[5];
+        let granule = u64::from_le_bytes(header[6..14].try_into().unwrap());
+        let serial = u32::from_le_bytes(header[14..18].try_into().unwrap());
+        let sequence = u32::from_le_bytes(header[18..22].try_into().unwrap());
+        let crc = u32::from_le_bytes(header[22..26].try_into().unwrap());
+
+        let mut lacings = vec![0u8; header[26] as usize];
+        self.reader.read_exact(&mut lacings)?;
+
+        let payload_size = lacings.iter().map(|x| *x as usize).sum();
+        let mut payload = vec![0u8; payload_size];
+        self.reader.read_exact(&mut payload)?;
+
+        let mut page = Vec::with_capacity(HEADER_SIZE + lacings.len() + payload.len());
+        page.extend_from_slice(&header);
+        page[22..26].copy_from_slice(&[0, 0, 0, 0]);
+        page.extend_from_slice(&lacings);
+        page.extend_from_slice(&payload);
+
+        if vorbis_crc32(&page) != crc {
+            return Err(Error::new(ErrorKind::InvalidData, format!("CRC mismatch on page {sequence}")));
+        }
+
+        let continuation = (header_type & 0x01) != 0;
+        if !continuation && !self.partial.is_empty() {
+            warn!("Discarding {} bytes of an unterminated packet before page {sequence}", self.partial.len());
+            self.partial.clear();
+        }
+
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        for lacing in lacings {
+            let lacing = lacing as usize;
+            self.partial.extend_from_slice(&payload[offset..offset + lacing]);
+            offset += lacing;
+
+            if lacing < 255 {
+                packets.push(std::mem::take(&mut self.partial));
+            }
+        }
+
+        Ok(Some(OggPage {
+            header: OggHeader {
+                continuation,
+                begin_stream: (header_type & 0x02) != 0,
+                end_stream: (header_type & 0x04) != 0,
+                granule,
+                serial,
+                sequence,
+            },
+            packets,
+        }))
+    }
+}
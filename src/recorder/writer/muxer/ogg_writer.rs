@@ -0,0 +1,84 @@
+use std::io::Write;
+use std::path::PathBuf;
+use crate::recorder::error::{MuxError, RecorderError};
+use crate::recorder::writer::muxer::ogg::{OggHeader, OggSegments};
+
+const MAX_SAMPLES_PER_PAGE: usize = 200_000;
+
+/// Writes packets into Ogg pages using the same layout rules as `StreamWriter`.
+pub struct OggPageWriter<W: Write> {
+    writer: W,
+    /// The file being written, for errors.
+    path: PathBuf,
+    serial: u32,
+    sequence: u32,
+    granule: u64,
+    segments: OggSegments,
+    payload: Vec<u8>,
+    page_samples: usize,
+}
+
+impl<W: Write> OggPageWriter<W> {
+    /// Writes a stream with the given serial number to `writer`, which writes to the file at `path`.
+    pub fn new(writer: W, path: PathBuf, serial: u32) -> Self {
+        Self {
+            writer,
+            path,
+            serial,
+            sequence: 0,
+            granule: 0,
+            segments: OggSegments::new(),
+            payload: Vec::new(),
+            page_samples: 0,
+        }
+    }
+
+    /// Writes a header packet on a page of its own. The first one begins the stream.
+    pub fn write_header_packet(&mut self, packet: &[u8]) -> Result<(), RecorderError> {
+        self.segments.push_packet(packet.len());
+        self.payload.extend_from_slice(packet);
+        self.flush(self.sequence == 0, false)
+    }
+
+    /// Adds an audio packet of `samples` samples, writing out a page first if it is full.
+    pub fn push(&mut self, packet: &[u8], samples: usize) -> Result<(), RecorderError> {
+        if self.segments.would_split(packet.len()).is_some() || self.page_samples > MAX_SAMPLES_PER_PAGE {
+            self.flush(false, false)?;
+        }
+
+        self.segments.push_packet(packet.len());
+        self.payload.extend_from_slice(packet);
+        self.page_samples += samples;
+
+        Ok(())
+    }
+
+    /// Writes the last page, marked as the end of the stream, and hands back the underlying writer.
+    pub fn finish(mut self) -> Result<W, RecorderError> {
+        self.flush(false, true)?;
+        Ok(self.writer)
+    }
+
+    fn flush(&mut self, begin_stream: bool, end_stream: bool) -> Result<(), RecorderError> {
+        self.granule += self.page_samples as u64;
+
+        let header = OggHeader {
+            continuation: false,
+            begin_stream,
+            end_stream,
+            granule: self.granule,
+            serial: self.serial,
+            sequence: self.sequence,
+        };
+
+        let page = header.build_page(&self.segments, &self.payload).ok_or(MuxError::PageTooLarge)?;
+        self.writer.write_all(&page).map_err(|e| RecorderError::io("write to", &self.path, e))?;
+
+        self.sequence += 1;
+        self.segments.clear();
+        self.payload.clear();
+        self.page_samples = 0;
+
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,168 @@
+use crate::recorder::error::MuxError;
+use crate::recorder::writer::muxer::opus_toc::{FrameCount, OpusToc};
+
+/// The maximum size of a single compressed frame (RFC 6716 §3.2.1, R2).
+const MAX_FRAME_SIZE: usize = 1275;
+/// The maximum duration of a packet, in units of 0.1 ms (RFC 6716 §3.2.5, R5).
+const MAX_PACKET_DURATION: usize = 1200;
+
+/// A validated Opus packet, split into its individual compressed frames as per RFC 6716 §3.2.
+#[derive(Debug)]
+pub struct OpusPacket<'a> {
+    /// The packet's TOC byte.
+    pub toc: OpusToc,
+    /// Compressed frames; a zero-length frame signals DTX or a lost frame.
+    pub frames: Vec<&'a [u8]>,
+    /// Number of trailing padding bytes (code 3 only).
+    #[allow(dead_code)]
+    pub padding: usize,
+}
+
+impl<'a> OpusPacket<'a> {
+    /// Splits `data` into frames, checking it against the rules of RFC 6716 §3.4.
+    pub fn parse(data: &'a [u8]) -> Result<Self, MuxError> {
+        let (&toc_byte, body) = data.split_first().ok_or(MuxError::EmptyPacket)?;
+        let toc = OpusToc::from(toc_byte);
+
+        let mut padding = 0;
+
+        let frames = match toc.frame_count {
+            FrameCount::One => {
+                vec![body]
+            }
+            FrameCount::TwoEqual => {
+                if body.len() % 2 != 0 {
+                    return Err(MuxError::InvalidToc(format!("Code 1 packet has odd payload length: {}", body.len())));
+                }
+                let (first, second) = body.split_at(body.len() / 2);
+                vec![first, second]
+            }
+            FrameCount::TwoDifferent => {
+                let (first_len, used) = read_frame_length(body)?;
+                let body = &body[used..];
+                if first_len > body.len() {
+                    return Err(MuxError::FrameLength { declared: first_len, available: body.len() });
+                }
+                let (first, second) = body.split_at(first_len);
+                vec![first, second]
+            }
+            FrameCount::Arbitrary => {
+                let (&count_byte, mut body) = body.split_first().ok_or(MuxError::Truncated("code 3 frame count"))?;
+
+                let vbr = (count_byte & 0b1000_0000) != 0;
+                let has_padding = (count_byte & 0b0100_0000) != 0;
+                let count = (count_byte & 0b0011_1111) as usize;
+
+                if count == 0 {
+                    return Err(MuxError::InvalidToc("Code 3 packet has a frame count of 0".to_string()));
+                }
+
+                let duration = count * toc.frame_size.to_10_000_factor();
+                if duration > MAX_PACKET_DURATION {
+                    return Err(MuxError::InvalidToc(format!("Code 3 packet is too long: {count} frames ({} ms)", duration as f32 / 10.0)));
+                }
+
+                if has_padding {
+                    loop {
+                        let (&pad_byte, rest) = body.split_first().ok_or(MuxError::Truncated("code 3 padding length"))?;
+                        body = rest;
+                        if pad_byte == 255 {
+                            padding += 254;
+                        } else {
+                            padding += pad_byte as usize;
+                            break;
+                        }
+                    }
+
+                    if padding > body.len() {
+                        return Err(MuxError::FrameLength { declared: padding, available: body.len() });
+                    }
+                    body = &body[..body.len() - padding];
+                }
+
+                if vbr {
+                    let mut lengths = Vec::with_capacity(count);
+                    for _ in 0..count - 1 {
+                        let (len, used) = read_frame_length(body)?;
+                        body = &body[used..];
+                        lengths.push(len);
+                    }
+
+                    let declared: usize = lengths.iter().sum();
+                    if declared > body.len() {
+                        return Err(MuxError::FrameLength { declared, available: body.len() });
+                    }
+                    lengths.push(body.len() - declared);
+
+                    let mut frames = Vec::with_capacity(count);
+                    for len in lengths {
+                        let (frame, rest) = body.split_at(len);
+                        frames.push(frame);
+                        body = rest;
+                    }
+                    frames
+                } else {
+                    if body.len() % count != 0 {
+                        return Err(MuxError::InvalidToc(format!("Code 3 CBR payload ({} bytes) is not a multiple of the frame count ({count})", body.len())));
+                    }
+                    let frame_len = body.len() / count;
+                    (0..count).map(|i| &body[i * frame_len..(i + 1) * frame_len]).collect()
+                }
+            }
+        };
+
+        if let Some(frame) = frames.iter().find(|f| f.len() > MAX_FRAME_SIZE) {
+            return Err(MuxError::FrameTooLarge(frame.len()));
+        }
+
+        Ok(Self {
+            toc,
+            frames,
+            padding,
+        })
+    }
+
+    /// Number of frames in the packet.
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Number of samples in the packet at 48 kHz, as used for Ogg Opus granule positions.
+    pub fn sample_count(&self) -> usize {
+        self.frame_count() * self.toc.samples_per_frame()
+    }
+}
+
+/// Reads a one- or two-byte frame length (RFC 6716 §3.2.1).
+/// Returns the length and the number of bytes consumed.
+fn read_frame_length(data: &[u8]) -> Result<(usize, usize), MuxError> {
+    match data {
+        [] => Err(MuxError::Truncated("frame length")),
+        [first @ 0..=251, ..] => Ok((*first as usize, 1)),
+        [_] => Err(MuxError::Truncated("second byte of frame length")),
+        [first, second, ..] => Ok((*second as usize * 4 + *first as usize, 2)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_frames() {
+        // Code 1: two 20 ms CELT frames of equal size.
+        let packet = OpusPacket::parse(&[0b1111_1001, 1, 2, 3, 4]).unwrap();
+        assert_eq!(packet.frames, [&[1, 2][..], &[3, 4][..]]);
+        assert_eq!(packet.sample_count(), 2 * 960);
+    }
+
+    #[test]
+    fn rejects_malformed_packets() {
+        assert_eq!(OpusPacket::parse(&[]).unwrap_err(), MuxError::EmptyPacket);
+        assert!(matches!(OpusPacket::parse(&[0b1111_1001, 1, 2, 3]).unwrap_err(), MuxError::InvalidToc(_)));
+        assert_eq!(OpusPacket::parse(&[0b1111_1010, 5, 1, 2]).unwrap_err(), MuxError::FrameLength { declared: 5, available: 2 });
+        assert_eq!(OpusPacket::parse(&[0b1111_1011]).unwrap_err(), MuxError::Truncated("code 3 frame count"));
+        assert_eq!(OpusPacket::parse(&[0b1111_1000; 1277]).unwrap_err(), MuxError::FrameTooLarge(1276));
+    }
+}
\ No newline at end of file
@@ -1,16 +1,26 @@
+/// The TOC byte that starts every Opus packet (RFC 6716 §3.1).
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct OpusToc {
+    /// The codec used.
     pub mode: OpusMode,
+    /// The audio bandwidth.
     pub bandwidth: Bandwidth,
+    /// Duration of each frame.
     pub frame_size: FrameSize,
+    /// Whether the frames are stereo.
     pub stereo: bool,
+    /// How many frames are in the packet.
     pub frame_count: FrameCount,
 }
 
+/// Which codec a packet was coded with.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpusMode {
+    /// SILK only, for speech.
     Silk,
+    /// SILK and CELT together.
     Hybrid,
+    /// CELT only.
     Celt,
 }
 
@@ -25,16 +35,23 @@ impl OpusMode {
     }
 }
 
+/// Audio bandwidth of a packet.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Bandwidth {
+    /// 4 kHz.
     Narrowband,
+    /// 6 kHz.
     Mediumband,
+    /// 8 kHz.
     Wideband,
+    /// 12 kHz.
     Superwideband,
+    /// 20 kHz.
     Fullband,
 }
 
 impl Bandwidth {
+    /// The sample rate needed to code this bandwidth.
     pub const fn sample_rate(self) -> u32 {
         match self {
             Bandwidth::Narrowband => 8_000,
@@ -57,18 +74,26 @@ impl Bandwidth {
     }
 }
 
+/// Duration of a single frame.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FrameSize {
+    /// 2.5 ms.
     Ms2_5,
+    /// 5 ms.
     Ms5,
+    /// 10 ms.
     Ms10,
+    /// 20 ms.
     Ms20,
+    /// 40 ms.
     Ms40,
+    /// 60 ms.
     Ms60,
 }
 
 impl FrameSize {
-    fn to_10_000_factor(self) -> usize {
+    /// Duration in units of 0.1 ms.
+    pub fn to_10_000_factor(self) -> usize {
         match self {
             FrameSize::Ms2_5 => 25,
             FrameSize::Ms5 => 50,
@@ -172,11 +197,16 @@ impl FrameSize {
     }
 }
 
+/// How many frames a packet holds, from the low bits of the TOC byte.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FrameCount {
+    /// A single frame.
     One,
+    /// Two frames of the same size.
     TwoEqual,
+    /// Two frames of different sizes.
     TwoDifferent,
+    /// Any number of frames, counted in the packet.
     Arbitrary,
 }
 
@@ -192,14 +222,19 @@ impl FrameCount {
     }
 }
 
+/// Ogg Opus granule positions are always counted at 48 kHz, regardless of the coded bandwidth.
+pub const GRANULE_SAMPLE_RATE: usize = 48_000;
+
 impl OpusToc {
-    pub fn sample_count(&self) -> usize {
-        let sample_rate: u32 = self.bandwidth.sample_rate();
+    /// Number of samples in a single frame at 48 kHz.
+    /// Use `OpusPacket::sample_count` to account for packets with more than one frame.
+    pub fn samples_per_frame(&self) -> usize {
         let frame_length = self.frame_size.to_10_000_factor();
 
-        ((sample_rate as usize) * frame_length) / 10_000
+        (GRANULE_SAMPLE_RATE * frame_length) / 10_000
     }
 
+    /// Parses a TOC byte.
     pub const fn from(value: u8) -> Self {
         let config = value >> 3;
         let frame_count_bits = value & 0b0000_0011;
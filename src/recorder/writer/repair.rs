@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use serenity::all::GuildId;
+use songbird::driver::opus::coder::{Decoder, Encoder};
+use songbird::driver::opus::MutSignals;
+use songbird::driver::opus::{Application, Channels, SampleRate};
+use crate::recorder::error::{MuxError, RecorderError};
+use crate::recorder::writer::muxer::ogg_reader::OggReader;
+use crate::recorder::writer::muxer::ogg_writer::OggPageWriter;
+use crate::recorder::writer::muxer::opus_packet::OpusPacket;
+
+/// Largest possible Opus packet duration (120 ms) at 48 kHz, per channel.
+const MAX_FRAME_SAMPLES: usize = 5_760;
+const CHANNELS: usize = 2;
+/// Generous upper bound on the size of a single re-encoded packet.
+const MAX_ENCODED_SIZE: usize = 4_000;
+
+/// Yields the audio packets of an Ogg Opus stream together with their start position in samples.
+struct PacketQueue<R: Read> {
+    reader: OggReader<R>,
+    /// The track being read, for errors.
+    path: PathBuf,
+    queue: VecDeque<Vec<u8>>,
+    serial: u32,
+}
+
+impl<R: Read> PacketQueue<R> {
+    fn fill(&mut self, count: usize) -> Result<(), RecorderError> {
+        while self.queue.len() < count {
+            match self.reader.read_page().map_err(|e| RecorderError::io("read", &self.path, e))? {
+                None => break,
+                Some(page) => {
+                    self.serial = page.header.serial;
+                    self.queue.extend(page.packets);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Vec<u8>>, RecorderError> {
+        self.fill(1)?;
+        Ok(self.queue.pop_front())
+    }
+
+    fn peek(&mut self) -> Result<Option<&Vec<u8>>, RecorderError> {
+        self.fill(1)?;
+        Ok(self.queue.front())
+    }
+}
+
+fn output_signals(output: &mut [i16], position: u64) -> Result<MutSignals<'_, i16>, MuxError> {
+    output.try_into().map_err(|e| codec_error(position, format!("Unusable output buffer: {e:?}")))
+}
+
+fn codec_error(position: u64, problem: String) -> MuxError {
+    MuxError::Codec { position, problem }
+}
+
+/// Path of the repaired copy of a track, e.g. `01_name_123.repaired.opus`.
+pub fn repaired_path(raw_path: &Path) -> PathBuf {
+    raw_path.with_extension("repaired.opus")
+}
+
+/// Rebuilds the packets that fall within `losses` (in samples since the start of the track) and writes
+/// the result alongside the raw track.
+///
+/// The frame immediately before a received packet is recovered from that packet's in-band FEC (LBRR)
+/// data when present; any other lost frames are filled using the decoder's packet loss concealment.
+/// Every other packet is copied through untouched.
+pub fn repair_track(raw_path: &Path, losses: &[Range<u64>], guild_id: GuildId) -> Result<PathBuf, RecorderError> {
+    let out_path = repaired_path(raw_path);
+    debug!("[{guild_id}] Repairing {} lost range(s) in {}", losses.len(), raw_path.display());
+
+    let raw_file = File::open(raw_path).map_err(|e| RecorderError::io("open", raw_path, e))?;
+    let out_file = File::create(&out_path).map_err(|e| RecorderError::io("create", &out_path, e))?;
+
+    let mut packets = PacketQueue {
+        reader: OggReader::new(BufReader::new(raw_file)),
+        path: raw_path.to_path_buf(),
+        queue: VecDeque::new(),
+        serial: 0,
+    };
+
+    let id_header = packets.next()?.ok_or(MuxError::MissingHeader("ID"))?;
+    let comment_header = packets.next()?.ok_or(MuxError::MissingHeader("comment"))?;
+
+    let mut writer = OggPageWriter::new(BufWriter::new(out_file), out_path.clone(), packets.serial);
+
+    writer.write_header_packet(&id_header)?;
+    writer.write_header_packet(&comment_header)?;
+
+    let mut decoder = Decoder::new(SampleRate::Hz48000, Channels::Stereo).map_err(|e| codec_error(0, format!("Failed to create decoder: {e}")))?;
+    let encoder = Encoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Audio).map_err(|e| codec_error(0, format!("Failed to create encoder: {e}")))?;
+
+    let mut pcm = vec![0i16; MAX_FRAME_SAMPLES * CHANNELS];
+    let mut encoded = vec![0u8; MAX_ENCODED_SIZE];
+
+    let is_lost = |position: u64| losses.iter().any(|x| x.contains(&position));
+
+    let mut position = 0u64;
+    let mut repaired = 0;
+
+    while let Some(packet) = packets.next()? {
+        let samples = OpusPacket::parse(&packet)?.sample_count();
+
+        if is_lost(position) {
+            let output = &mut pcm[..samples * CHANNELS];
+
+            let next_is_received = !is_lost(position + samples as u64);
+            let concealed = match packets.peek()? {
+                Some(next) if next_is_received => {
+                    let next = next.as_slice().try_into().map_err(|e| codec_error(position, format!("Unusable next packet: {e:?}")))?;
+                    decoder.decode(Some(next), output_signals(output, position)?, true)
+                }
+                _ => decoder.decode(None, output_signals(output, position)?, false),
+            };
+
+            match concealed {
+                Ok(_) => {
+                    let size = encoder.encode(output, &mut encoded).map_err(|e| codec_error(position, format!("Failed to encode concealed frame: {e}")))?;
+                    writer.push(&encoded[..size], samples)?;
+                    repaired += 1;
+                }
+                Err(e) => {
+                    warn!("[{guild_id}] Failed to conceal frame at sample {position}: {e:?}");
+                    writer.push(&packet, samples)?;
+                }
+            }
+        } else {
+            // Decode everything, even though the output is discarded, so the decoder state is right for concealment.
+            let input = packet.as_slice().try_into().map_err(|e| codec_error(position, format!("Unusable packet: {e:?}")))?;
+            if let Err(e) = decoder.decode(Some(input), output_signals(&mut pcm, position)?, false) {
+                warn!("[{guild_id}] Failed to decode packet at sample {position}: {e:?}");
+            }
+            writer.push(&packet, samples)?;
+        }
+
+        position += samples as u64;
+    }
+
+    let mut out_file = writer.finish()?;
+    out_file.flush().map_err(|e| RecorderError::io("write to", &out_path, e))?;
+    out_file.get_ref().sync_all().map_err(|e| RecorderError::io("sync", &out_path, e))?;
+
+    info!("[{guild_id}] Repaired {repaired} frame(s) into {}", out_path.display());
+
+    Ok(out_path)
+}
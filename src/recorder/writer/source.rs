@@ -0,0 +1,52 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use serenity::async_trait;
+use tokio::sync::mpsc;
+use crate::recorder::writer::VoiceUpdateType;
+
+/// An update along with where it happened on the recording's timeline, in samples since the recording started.
+#[derive(Debug, PartialEq)]
+pub struct TimedUpdate {
+    /// Samples since the recording started.
+    pub at: u64,
+    /// What happened.
+    pub update: VoiceUpdateType,
+}
+
+/// Something producing the speaking and packet events of a call, such as a live voice connection or a packet dump.
+/// A call's writer task reads from one of these until it runs dry.
+#[async_trait]
+pub trait VoiceSource: Send {
+    /// The next update, or `None` once the source has ended.
+    async fn next(&mut self) -> Option<TimedUpdate>;
+}
+
+/// Updates queued by `Writer::send` from a live call.
+#[derive(Debug)]
+pub struct QueueSource {
+    rx: mpsc::UnboundedReceiver<TimedUpdate>,
+    /// Voice ticks in the queue, shared with the sender so that it knows when to start dropping them.
+    queued_ticks: Arc<AtomicUsize>,
+}
+
+impl QueueSource {
+    /// Reads updates from `rx`, counting voice ticks off `queued_ticks` as they are taken.
+    pub fn new(rx: mpsc::UnboundedReceiver<TimedUpdate>, queued_ticks: Arc<AtomicUsize>) -> Self {
+        Self {
+            rx,
+            queued_ticks,
+        }
+    }
+}
+
+#[async_trait]
+impl VoiceSource for QueueSource {
+    async fn next(&mut self) -> Option<TimedUpdate> {
+        let update = self.rx.recv().await?;
+        if let VoiceUpdateType::Opus(_) = update.update {
+            self.queued_ticks.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        Some(update)
+    }
+}
\ No newline at end of file
@@ -1,21 +1,50 @@
-use std::cmp::min;
-use std::path::PathBuf;
-use std::sync::Mutex;
-use rand::Rng;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use sha2::{Digest, Sha256};
+use chrono::SecondsFormat;
 use serenity::all::{GuildId, UserId};
-use tokio::fs::File;
+use tokio::fs::{File, OpenOptions};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex as AsyncMutex;
-use crate::recorder::writer::muxer::ogg::{OggHeader, OggSegments, MAX_SEGMENTS_PER_FRAME};
+use crate::recorder::{RecorderConfig, RecordingMetadata, SegmentPolicy};
+use crate::recorder::track_name::ReservedNames;
+use crate::recorder::durability::SyncPolicy;
+use crate::recorder::metrics::RecorderMetrics;
+use crate::recorder::error::{MuxError, RecorderError};
+use crate::recorder::writer::UserUpdate;
+use crate::recorder::writer::muxer::ogg::{OggHeader, OggSegments};
 use crate::recorder::writer::muxer::ogg_opus::{CommentHeader, IdHeader, MappingFamily, PRESKIP_DEFAULT};
-use crate::recorder::writer::muxer::opus_toc::{Bandwidth, OpusToc};
+use crate::recorder::writer::muxer::opus_packet::OpusPacket;
+use crate::recorder::writer::muxer::opus_toc::{Bandwidth, OpusToc, GRANULE_SAMPLE_RATE};
 
 const DISCORD_BANDWIDTH: Bandwidth = Bandwidth::Fullband;
 const SILENCE_PACKET: [u8; 3] = [0xF8, 0xFF, 0xFE];
 const SILENCE_TOC: OpusToc = OpusToc::from(SILENCE_PACKET[0]);
 const MAX_SAMPLES_PER_PAGE: usize = 200_000;
+/// CELT silence in every frame size, longest first, so that any gap can be filled to within 2.5 ms.
+const SILENCE_FRAMES: [[u8; 3]; 4] = [
+    SILENCE_PACKET,     // 20 ms
+    [0xF0, 0xFF, 0xFE], // 10 ms
+    [0xE8, 0xFF, 0xFE], // 5 ms
+    [0xE0, 0xFF, 0xFE], // 2.5 ms
+];
+/// If a stream's RTP clock disagrees with the wall clock by more than this (1 s), it is re-anchored.
+const RESYNC_THRESHOLD: i64 = GRANULE_SAMPLE_RATE as i64;
+
+/// Number of 48 kHz samples elapsed since `epoch`.
+pub fn elapsed_samples(epoch: Instant) -> u64 {
+    elapsed_samples_at(epoch, Instant::now())
+}
 
-#[derive(Debug)]
+/// Number of 48 kHz samples between `epoch` and `at`.
+pub fn elapsed_samples_at(epoch: Instant, at: Instant) -> u64 {
+    (at.saturating_duration_since(epoch).as_nanos() * GRANULE_SAMPLE_RATE as u128 / 1_000_000_000) as u64
+}
+
+#[derive(Debug, Default)]
 pub struct PacketBuffer {
     pub opus: Vec<u8>,
     pub tocs: Vec<OpusToc>,
@@ -41,91 +70,277 @@ impl PacketBuffer {
     }
 }
 
+/// The RTP header fields of the last packet written, used to place the next packet on the timeline.
+#[derive(Debug)]
+pub struct RtpTiming {
+    timestamp: u32,
+    sequence: u16,
+    /// Position of the packet in samples since the recording started.
+    position: u64,
+    samples: u64,
+}
+
+/// One file of a track. Tracks only have more than one when segmenting is enabled.
+#[derive(Clone, Debug)]
+pub struct Segment {
+    /// The segment's file.
+    pub path: PathBuf,
+    /// Position of the segment's first sample, in samples since the recording started.
+    pub offset: u64,
+    /// Samples of audio in the segment.
+    pub samples: u64,
+    /// Sample ranges within this segment that were filled with silence because packets were lost.
+    pub losses: Vec<Range<u64>>,
+}
+
 #[derive(Debug)]
 pub struct OpusState {
+    /// Ogg serial of the current segment.
+    serial: u32,
     sequence: u32,
     granule: u64,
-    tick_count: usize,
+    rtp: Option<RtpTiming>,
+    /// Sample ranges that were filled with silence because packets were lost.
+    losses: Vec<Range<u64>>,
     started: bool,
     packet_buffer: PacketBuffer,
+    /// Pages written since the file was last synced to disk.
+    unsynced_pages: u32,
+    last_sync: Instant,
+    /// Every segment written so far; the last one is the file currently being written.
+    segments: Vec<Segment>,
+    /// Bytes written to the current segment.
+    segment_bytes: u64,
+    /// Recently received packets, kept for `/clip`.
+    history: VecDeque<ClipPacket>,
+    /// Position from which the segment length is measured; only differs from the segment start if a rollover failed.
+    roll_from: u64,
 }
 
+impl OpusState {
+    /// Number of samples written or buffered so far.
+    fn position(&self) -> u64 {
+        self.granule + self.packet_buffer.total_samples as u64
+    }
+
+    /// Position at which the current segment started.
+    fn segment_start(&self) -> u64 {
+        self.segments.last().map(|x| x.offset).unwrap_or(0)
+    }
+}
+
+/// A received packet kept in memory so that recent audio can be clipped without reading the track back.
+#[derive(Clone, Debug)]
+pub struct ClipPacket {
+    /// Position of the packet in samples since the recording started.
+    pub position: u64,
+    pub opus: Vec<u8>,
+    pub samples: usize,
+}
+
+/// The silence packets, longest first, that fill `samples` to within 2.5 ms.
+pub fn silence_frames(samples: u64) -> Vec<&'static [u8; 3]> {
+    let mut frames = Vec::new();
+    let mut samples_left = samples;
+
+    for frame in &SILENCE_FRAMES {
+        let frame_samples = OpusToc::from(frame[0]).samples_per_frame() as u64;
+
+        while samples_left >= frame_samples {
+            frames.push(frame);
+            samples_left -= frame_samples;
+        }
+    }
+
+    frames
+}
+
+/// The OpusHead written at the start of every track.
+pub fn id_header() -> IdHeader {
+    IdHeader {
+        channel_count: 2,
+        preskip: PRESKIP_DEFAULT,
+        input_sample_rate: DISCORD_BANDWIDTH.sample_rate(),
+        gain: 0,
+        mapping_family: MappingFamily::Rtp,
+    }
+}
+
+/// Ogg serial of a segment. Derived from the session rather than random, so that replays are reproducible.
+fn stream_serial(session_id: &str, ssrc: u32, segment: usize) -> u32 {
+    let mut hasher = Sha256::new();
+    hasher.update(session_id.as_bytes());
+    hasher.update(ssrc.to_le_bytes());
+    hasher.update((segment as u64).to_le_bytes());
+
+    let digest = hasher.finalize();
+    u32::from_le_bytes([digest[0], digest[1], digest[2], digest[3]])
+}
+
+/// Name of a numbered segment of a track, e.g. `01_name_123.seg002.opus`.
+fn segment_name(file_path: &Path, index: usize) -> String {
+    let stem = file_path.file_stem().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    format!("{stem}.seg{index:03}.opus")
+}
+
+/// Writes one speaker's packets into an Ogg Opus track, keeping it in line with the rest of the call.
 #[derive(Debug)]
 pub struct StreamWriter {
     guild_id: GuildId,
     user_id: UserId,
-    serial: u32,
+    username: Option<String>,
+    display_name: Option<String>,
+    ssrc: u32,
     state: Mutex<OpusState>,
     file: AsyncMutex<File>,
     file_path: PathBuf,
+    comments: Vec<String>,
+    session_id: String,
+    sync_policy: SyncPolicy,
+    metrics: Arc<RecorderMetrics>,
+    segment_policy: SegmentPolicy,
+    /// Names taken in the recording directory, which every segment is reserved in before it is created.
+    file_names: Arc<ReservedNames>,
+    /// How many samples of received packets to keep in `history`.
+    history_window: u64,
 }
 
 impl StreamWriter {
     // TODO: Pass in a TOC so we can move away from assuming constant Discord bandwidths?
-    pub async fn new(guild_id: GuildId, user_id: UserId, user_name: Option<String>, output_dir: PathBuf) -> Option<Self> {
-        let rand_serial = rand::rng().random::<u32>();
+    /// Creates the writer of a user's track at `file_path` and reserves its name.
+    ///
+    /// The file isn't opened until `start` is called.
+    pub async fn new(metadata: &RecordingMetadata, user_update: &UserUpdate, file_path: PathBuf, config: &RecorderConfig, file_names: Arc<ReservedNames>, metrics: Arc<RecorderMetrics>) -> Result<Self, RecorderError> {
+        let guild_id = metadata.guild_id;
+        let user_id = user_update.user;
+        let comments = build_comments(metadata, user_update);
+
+        tokio::fs::create_dir_all(&metadata.output_dir).await
+            .map_err(|e| RecorderError::io("create directory", &metadata.output_dir, e))?;
+
+        let first_path = if config.segment_policy.is_enabled() {
+            file_names.reserve(&metadata.output_dir, &segment_name(&file_path, 1)).await
+        } else {
+            file_path.clone()
+        };
 
-        if let Err(e) = tokio::fs::create_dir_all(output_dir.clone()).await {
-            error!("[{guild_id}] <{user_id}> Failed to create parent directory: {e:?}");
-            return None;
-        }
+        trace!("[{guild_id}] <{user_id}> Creating output file: {}", first_path.display());
 
-        let file_path = match user_name {
-            None => output_dir.join(user_id.to_string() + ".opus"),
-            Some(name) => output_dir.join(name + ".opus"),
+        let file = create_file(&first_path).await
+            .map_err(|e| RecorderError::io("create", &first_path, e))?;
+
+        let state = OpusState {
+            serial: stream_serial(&metadata.session_id, user_update.ssrc, 1),
+            sequence: 0,
+            granule: 0,
+            rtp: None,
+            losses: Vec::new(),
+            started: false,
+            packet_buffer: PacketBuffer::new(),
+            unsynced_pages: 0,
+            last_sync: Instant::now(),
+            segments: vec![Segment {
+                path: first_path,
+                offset: 0,
+                samples: 0,
+                losses: Vec::new(),
+            }],
+            segment_bytes: 0,
+            roll_from: 0,
+            history: VecDeque::new(),
         };
 
-        trace!("[{guild_id}] <{user_id}> Creating output file: {}", file_path.display());
-
-        match File::create(&file_path).await {
-            Ok(file) => {
-                let state = OpusState {
-                    sequence: 0,
-                    granule: 0,
-                    tick_count: 0,
-                    started: false,
-                    packet_buffer: PacketBuffer::new(),
-                };
-
-                let stream = Self {
-                    guild_id,
-                    user_id,
-                    serial: rand_serial,
-                    state: Mutex::new(state),
-                    file:  AsyncMutex::new(file),
-                    file_path,
-                };
-
-                stream.start().await;
-
-                Some(stream)
-            }
-            Err(e) => {
-                error!("[{guild_id}] <{user_id}> Failed to create new file {}: {e:?}", file_path.display());
-                None
-            }
-        }
+        let stream = Self {
+            guild_id,
+            user_id,
+            username: user_update.username.clone(),
+            display_name: user_update.display_name.clone(),
+            ssrc: user_update.ssrc,
+            state: Mutex::new(state),
+            file:  AsyncMutex::new(file),
+            file_path,
+            comments,
+            session_id: metadata.session_id.clone(),
+            sync_policy: config.sync_policy,
+            metrics,
+            segment_policy: config.segment_policy,
+            file_names,
+            history_window: (config.clip_history.as_secs_f64() * GRANULE_SAMPLE_RATE as f64) as u64,
+        };
+
+        stream.start().await?;
+
+        Ok(stream)
+    }
+
+    /// The SSRC the track is received on.
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    /// The user whose track this is.
+    pub fn user_id(&self) -> UserId {
+        self.user_id
+    }
+
+    /// The user's Discord username, if known.
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
     }
 
-    pub async fn start(&self) {
-        debug!("[{}] <{}> Starting file: {}", self.guild_id, self.user_id, self.file_path.display());
+    /// The user's display name in the guild, if known.
+    pub fn display_name(&self) -> Option<&str> {
+        self.display_name.as_deref()
+    }
+
+    /// Path of the track's first segment.
+    pub fn file_path(&self) -> &PathBuf {
+        &self.file_path
+    }
+
+    /// Tags written to the track's comment header.
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    /// Every file written for this track, with losses given relative to the start of each file.
+    pub fn segments(&self) -> Vec<Segment> {
+        let state = self.state.lock().unwrap();
+
+        let mut segments = state.segments.clone();
+        if let Some(current) = segments.last_mut() {
+            current.samples = state.position() - current.offset;
+        }
+
+        for segment in &mut segments {
+            let end = segment.offset + segment.samples;
+            segment.losses = state.losses.iter()
+                .filter(|x| x.start < end && x.end > segment.offset)
+                .map(|x| x.start.max(segment.offset) - segment.offset..x.end.min(end) - segment.offset)
+                .collect();
+        }
 
-        let opus_id_header = IdHeader {
-            channel_count: 2,
-            preskip: PRESKIP_DEFAULT,
-            input_sample_rate: DISCORD_BANDWIDTH.sample_rate(),
-            gain: 0,
-            mapping_family: MappingFamily::Rtp,
+        segments
+    }
+
+    /// Opens the current segment and writes its Ogg Opus headers.
+    pub async fn start(&self) -> Result<(), RecorderError> {
+        let (serial, segment_index, segment) = {
+            let state = self.state.lock().unwrap();
+            let segment = state.segments.last().cloned().ok_or_else(|| RecorderError::Mux(MuxError::NoSegments))?;
+            (state.serial, state.segments.len(), segment)
         };
 
-        let opus_id_data = opus_id_header.build();
+        debug!("[{}] <{}> Starting file: {}", self.guild_id, self.user_id, segment.path.display());
+
+        let opus_id_data = id_header().build();
 
         let id_page_header = OggHeader {
             continuation: false,
             begin_stream: true,
             end_stream: false,
             granule: 0,
-            serial: self.serial,
+            serial,
             sequence: 0,
         };
 
@@ -134,22 +349,21 @@ impl StreamWriter {
 
         trace!("[{}] <{}> ID page header: {id_page_header:?}", self.guild_id, self.user_id);
 
-        let id_page = match id_page_header.build_page(&id_page_segments, opus_id_data.as_slice()) {
-            Some(x) => x,
-            None => {
-                error!("[{}] <{}> Failed to build Opus ID page!", self.guild_id, self.user_id);
-                return;
-            }
-        };
+        let id_page = id_page_header.build_page(&id_page_segments, opus_id_data.as_slice())
+            .ok_or_else(|| RecorderError::Mux(MuxError::PageTooLarge))?;
 
-        {
-            let mut file =  self.file.lock().await;
-            file.write_all(id_page.as_slice()).await.unwrap();
+        self.write_page(&id_page, &segment.path).await?;
+        self.state.lock().unwrap().segment_bytes += id_page.len() as u64;
+
+        let mut comments = self.comments.clone();
+        if self.segment_policy.is_enabled() {
+            comments.push(format!("SEGMENT={segment_index}"));
+            comments.push(format!("SEGMENT_OFFSET={}", segment.offset));
         }
 
         let opus_comment_header = CommentHeader {
             vendor: "disrecord".to_string(),
-            comments: vec![]
+            comments,
         };
 
         let opus_comment_data = opus_comment_header.build();
@@ -159,7 +373,7 @@ impl StreamWriter {
             begin_stream: false,
             end_stream: false,
             granule: 0,
-            serial: self.serial,
+            serial,
             sequence: 1,
         };
 
@@ -168,134 +382,320 @@ impl StreamWriter {
 
         trace!("[{}] <{}> Comment page header: {comment_page_header:?}", self.guild_id, self.user_id);
 
-        let comment_page = match comment_page_header.build_page(&comment_page_segments, opus_comment_data.as_slice()) {
-            Some(x) => x,
-            None => {
-                error!("[{}] <{}> Failed to build Opus ID page!", self.guild_id, self.user_id);
-                return;
-            }
-        };
+        let comment_page = comment_page_header.build_page(&comment_page_segments, opus_comment_data.as_slice())
+            .ok_or_else(|| RecorderError::Mux(MuxError::PageTooLarge))?;
 
-        {
-            let mut file =  self.file.lock().await;
-            file.write_all(comment_page.as_slice()).await.unwrap();
-        }
+        self.write_page(&comment_page, &segment.path).await?;
+        self.state.lock().unwrap().segment_bytes += comment_page.len() as u64;
 
         {
             let mut state = self.state.lock().unwrap();
             state.started = true;
             state.sequence = 2;
         }
-    }
-
-    pub async fn fill_silence(&self, ticks: usize) {
-        let (mut sequence, mut last_granule) = {
-            let state = self.state.lock().unwrap();
-            (state.sequence, state.granule)
-        };
-
-        let mut packets_left = ticks;
-
-        while packets_left > 0 {
-            let page_packets = min(packets_left, MAX_SEGMENTS_PER_FRAME);
-
-            let mut opus_data: Vec<u8> = Vec::new();
-            let mut opus_segments = OggSegments::new();
-            for _ in 0..page_packets {
-                opus_data.extend_from_slice(&SILENCE_PACKET);
-                opus_segments.push_packet(SILENCE_PACKET.len());
-                last_granule += SILENCE_TOC.sample_count() as u64;
-            }
 
-            let page_header = OggHeader {
-                continuation: false,
-                begin_stream: false,
-                end_stream: false,
-                granule: last_granule as u64,
-                serial: self.serial,
-                sequence,
-            };
-
-            packets_left -= page_packets;
-            sequence += 1;
-
-            let page_data = page_header.build_page(&opus_segments, opus_data.as_slice()).unwrap();
+        Ok(())
+    }
 
-            {
-                let mut file =  self.file.lock().await;
-                file.write_all(page_data.as_slice()).await.unwrap();
-            }
-        }
+    async fn write_page(&self, page: &[u8], path: &Path) -> Result<(), RecorderError> {
+        let mut file = self.file.lock().await;
+        file.write_all(page).await.map_err(|e| RecorderError::io("write to", path, e))?;
+        self.metrics.page_written(self.guild_id, self.user_id, self.ssrc, page.len());
+        Ok(())
     }
 
-    async fn dump(&self, finalize: bool) {
-        let page_data = {
+    async fn dump(&self, finalize: bool) -> Result<(), RecorderError> {
+        let (page_data, sync, path) = {
             let mut state = self.state.lock().unwrap();
 
             let granule = state.granule + state.packet_buffer.total_samples as u64;
 
             trace!("[{}] <{}> Dumping Ogg page... (granule: {granule})", self.guild_id, self.user_id);
 
+            // Granules restart from zero in every segment.
             let page_header = OggHeader {
                 continuation: false,
                 begin_stream: false,
                 end_stream: finalize,
-                granule,
-                serial: self.serial,
+                granule: granule - state.segment_start(),
+                serial: state.serial,
                 sequence: state.sequence,
             };
 
-            let page_data = page_header.build_page(&state.packet_buffer.segments, state.packet_buffer.opus.as_slice()).unwrap();
+            let page_data = page_header.build_page(&state.packet_buffer.segments, state.packet_buffer.opus.as_slice())
+                .ok_or_else(|| RecorderError::Mux(MuxError::PageTooLarge))?;
 
             state.packet_buffer.clear();
             state.granule = granule;
             state.sequence += 1;
+            state.unsynced_pages += 1;
+            state.segment_bytes += page_data.len() as u64;
 
-            page_data
+            let sync = finalize || self.sync_policy.is_due(state.unsynced_pages, state.last_sync);
+            let path = state.segments.last().map(|x| x.path.clone()).unwrap_or_else(|| self.file_path.clone());
+            (page_data, sync, path)
         };
 
-        {
-            let mut file =  self.file.lock().await;
-            file.write_all(page_data.as_slice()).await.unwrap();
+        let mut file =  self.file.lock().await;
+        file.write_all(page_data.as_slice()).await.map_err(|e| RecorderError::io("write to", &path, e))?;
+        self.metrics.page_written(self.guild_id, self.user_id, self.ssrc, page_data.len());
+
+        if sync {
+            self.sync(&file).await;
         }
+
+        Ok(())
     }
 
-    pub async fn push_silence(&self, tick_count: usize) {
-        self.push(&SILENCE_PACKET, tick_count).await;
+    /// Flushes the track to disk, recording how long it took.
+    async fn sync(&self, file: &File) {
+        let start = Instant::now();
+        let res = file.sync_data().await;
+        let took = start.elapsed();
+
+        self.metrics.sync.record(took, res.is_ok());
+
+        match res {
+            Ok(_) => trace!("[{}] <{}> Synced {} in {took:?}", self.guild_id, self.user_id, self.file_path.display()),
+            Err(e) => error!("[{}] <{}> Failed to sync {}: {e:?}", self.guild_id, self.user_id, self.file_path.display()),
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.unsynced_pages = 0;
+        state.last_sync = Instant::now();
     }
 
-    pub async fn push(&self, opus_data: &[u8], tick_count: usize) {
-        let toc = OpusToc::from(*opus_data.first().unwrap());
+    /// Writes silence until the stream reaches `position`, in samples since the recording started.
+    pub async fn pad_to(&self, position: u64) -> Result<(), RecorderError> {
+        let written = self.state.lock().unwrap().position();
 
-        let dump = {
-            let state = &self.state.lock().unwrap();
-            state.packet_buffer.segments.would_split(opus_data.len()).is_some() || state.packet_buffer.total_samples > MAX_SAMPLES_PER_PAGE
+        if position <= written {
+            if position < written {
+                trace!("[{}] <{}> Packet overlaps written audio by {} samples", self.guild_id, self.user_id, written - position);
+            }
+            return Ok(());
+        }
+
+        for frame in silence_frames(position - written) {
+            let toc = OpusToc::from(frame[0]);
+            self.push_packet(frame, toc, toc.samples_per_frame()).await?;
+            self.metrics.silence_packets(self.guild_id, 1);
+        }
+
+        Ok(())
+    }
+
+    /// Places a packet on the timeline by its RTP timestamp, filling any gap since the previous packet with silence.
+    /// `now` is when the packet arrived, in samples since the recording started.
+    pub async fn push(&self, opus_data: &[u8], timestamp: u32, sequence: u16, now: u64) -> Result<(), RecorderError> {
+        let (opus_data, toc, sample_count) = match OpusPacket::parse(opus_data) {
+            Ok(packet) => (opus_data, packet.toc, packet.sample_count()),
+            Err(e) => {
+                // Write silence in its place so the stream stays aligned with the other users.
+                warn!("[{}] <{}> Rejecting malformed Opus packet: {e}", self.guild_id, self.user_id);
+                (SILENCE_PACKET.as_slice(), SILENCE_TOC, SILENCE_TOC.samples_per_frame())
+            }
+        };
+
+        let position = {
+            let mut state = self.state.lock().unwrap();
+            let mut loss = None;
+
+            let position = match &state.rtp {
+                None => {
+                    debug!("[{}] <{}> Anchoring RTP timestamp {timestamp} at sample {now}", self.guild_id, self.user_id);
+                    now
+                }
+                Some(last) => {
+                    let sequence_delta = sequence.wrapping_sub(last.sequence) as i16;
+                    if sequence_delta <= 0 {
+                        warn!("[{}] <{}> Dropping out-of-order packet! (last sequence: {}, got: {sequence})", self.guild_id, self.user_id, last.sequence);
+                        self.metrics.out_of_order_packet(self.guild_id);
+                        return Ok(());
+                    }
+                    if sequence_delta > 1 {
+                        debug!("[{}] <{}> Lost {} packet(s) before sequence {sequence}", self.guild_id, self.user_id, sequence_delta - 1);
+                        self.metrics.lost_packets(self.guild_id, sequence_delta as u64 - 1);
+                    }
+
+                    let timestamp_delta = timestamp.wrapping_sub(last.timestamp) as i32 as i64;
+                    let position = last.position as i64 + timestamp_delta;
+
+                    if (position - now as i64).abs() > RESYNC_THRESHOLD {
+                        warn!("[{}] <{}> RTP clock is {} samples away from the wall clock, re-anchoring", self.guild_id, self.user_id, position - now as i64);
+                        self.metrics.resync(self.guild_id);
+                        now
+                    } else {
+                        let position = position.max(0) as u64;
+
+                        let last_end = last.position + last.samples;
+                        if sequence_delta > 1 && position > last_end {
+                            loss = Some(last_end..position);
+                        }
+
+                        position
+                    }
+                }
+            };
+
+            if let Some(loss) = loss {
+                state.losses.push(loss);
+            }
+
+            state.rtp = Some(RtpTiming {
+                timestamp,
+                sequence,
+                position,
+                samples: sample_count as u64,
+            });
+
+            position
         };
 
-        if dump {
-            self.dump(false).await;
+        self.pad_to(position).await?;
+
+        if self.history_window > 0 {
+            let mut state = self.state.lock().unwrap();
+            let position = state.position();
+
+            state.history.push_back(ClipPacket {
+                position,
+                opus: opus_data.to_vec(),
+                samples: sample_count,
+            });
+            while state.history.front().is_some_and(|x| x.position + self.history_window < position) {
+                state.history.pop_front();
+            }
         }
 
-        let mut state = self.state.lock().unwrap();
+        self.push_packet(opus_data, toc, sample_count).await
+    }
+
+    /// The packets kept for clips that start at or after `since`, in samples since the recording started.
+    pub fn history_since(&self, since: u64) -> Vec<ClipPacket> {
+        let state = self.state.lock().unwrap();
+        state.history.iter().filter(|x| x.position >= since).cloned().collect()
+    }
+
+    async fn push_packet(&self, opus_data: &[u8], toc: OpusToc, sample_count: usize) -> Result<(), RecorderError> {
+        let (dump, roll_over) = {
+            let state = &self.state.lock().unwrap();
+            let dump = state.packet_buffer.segments.would_split(opus_data.len()).is_some() || state.packet_buffer.total_samples > MAX_SAMPLES_PER_PAGE;
 
-        if tick_count != state.tick_count + 1 {
-            warn!("[{}] <{}> Discontinuous tick count! (was: {}, now: {tick_count})", self.guild_id, self.user_id, state.tick_count);
+            let samples = state.position() - state.roll_from;
+            let bytes = state.segment_bytes + state.packet_buffer.opus.len() as u64;
+            (dump, samples > 0 && self.segment_policy.is_due(samples, bytes))
+        };
+
+        if roll_over {
+            self.roll_over().await?;
+        } else if dump {
+            self.dump(false).await?;
         }
-        state.tick_count = tick_count;
+
+        let mut state = self.state.lock().unwrap();
 
         let packet_buffer = &mut state.packet_buffer;
         packet_buffer.segments.push_packet(opus_data.len());
-        packet_buffer.total_samples += toc.sample_count();
+        packet_buffer.total_samples += sample_count;
         packet_buffer.tocs.push(toc);
         packet_buffer.opus.extend_from_slice(opus_data);
+
+        Ok(())
+    }
+
+    /// Ends the current segment and continues the track in a new file with fresh headers.
+    async fn roll_over(&self) -> Result<(), RecorderError> {
+        let (index, offset) = {
+            let state = self.state.lock().unwrap();
+            (state.segments.len() + 1, state.position())
+        };
+
+        let dir = self.file_path.parent().unwrap_or(Path::new(""));
+        let path = self.file_names.reserve(dir, &segment_name(&self.file_path, index)).await;
+        let file = match create_file(&path).await {
+            Ok(x) => x,
+            Err(e) => {
+                // Carry on in the current file, and try again once another segment's worth has been written.
+                error!("[{}] <{}> Failed to create segment {}: {e:?}", self.guild_id, self.user_id, path.display());
+                let mut state = self.state.lock().unwrap();
+                state.roll_from = offset;
+                state.segment_bytes = 0;
+                return Ok(());
+            }
+        };
+
+        self.dump(true).await?;
+
+        debug!("[{}] <{}> Rolling over to segment {index} at sample {offset}", self.guild_id, self.user_id);
+
+        *self.file.lock().await = file;
+
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(current) = state.segments.last_mut() {
+                current.samples = offset - current.offset;
+            }
+            state.segments.push(Segment {
+                path,
+                offset,
+                samples: 0,
+                losses: Vec::new(),
+            });
+            state.serial = stream_serial(&self.session_id, self.ssrc, index);
+            state.sequence = 0;
+            state.segment_bytes = 0;
+            state.roll_from = offset;
+        }
+
+        self.start().await
     }
 
-    pub async fn finish(&self) {
+    /// Writes the last page, ending the stream, and syncs the file to disk.
+    pub async fn finish(&self) -> Result<(), RecorderError> {
         trace!("[{}] <{}> Finishing StreamWriter...", self.guild_id, self.user_id);
         self.dump(true).await
     }
 }
 
+/// Builds the OpusTags for a track so that it stays self-describing outside the recording directory.
+fn build_comments(metadata: &RecordingMetadata, user_update: &UserUpdate) -> Vec<String> {
+    let mut comments = Vec::new();
+
+    if let Some(name) = user_update.display_name.as_ref().or(user_update.username.as_ref()) {
+        comments.push(format!("ARTIST={name}"));
+    }
+    if let Some(username) = &user_update.username {
+        comments.push(format!("DISCORD_USERNAME={username}"));
+    }
+    comments.push(format!("DISCORD_USER_ID={}", user_update.user));
+
+    if let Some(guild_name) = &metadata.guild_name {
+        comments.push(format!("DISCORD_GUILD={guild_name}"));
+    }
+    comments.push(format!("DISCORD_GUILD_ID={}", metadata.guild_id));
+
+    if let Some(channel_name) = &metadata.channel_name {
+        comments.push(format!("DISCORD_CHANNEL={channel_name}"));
+    }
+    comments.push(format!("DISCORD_CHANNEL_ID={}", metadata.channel_id));
+
+    comments.push(format!("DATE={}", metadata.started.to_rfc3339_opts(SecondsFormat::Secs, true)));
+    comments.push(format!("SESSION_ID={}", metadata.session_id));
+    comments.push(format!("ENCODER=disrecord {}", env!("CARGO_PKG_VERSION")));
+
+    comments
+}
+
+/// Creates a new file, never truncating an existing one; the caller is responsible for picking a free name.
+async fn create_file(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await
+}
+
 impl Drop for StreamWriter {
     fn drop(&mut self) {
         trace!("[{}] <{}> StreamWriter::drop", self.guild_id, self.user_id);
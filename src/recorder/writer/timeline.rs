@@ -0,0 +1,238 @@
+use std::path::Path;
+use std::time::Instant;
+use serde::Serialize;
+use serenity::all::{ChannelId, UserId, VoiceState};
+use crate::recorder::error::RecorderError;
+use crate::recorder::writer::stream_writer::elapsed_samples_at;
+
+/// Name of the timeline written as JSON.
+pub const TIMELINE_JSON_FILE: &str = "timeline.json";
+/// Name of the timeline written as text.
+pub const TIMELINE_TEXT_FILE: &str = "timeline.txt";
+
+/// Samples per voice tick (20 ms at 48 kHz).
+const TICK_SAMPLES: u64 = 960;
+
+/// What changed in a voice state event.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VoiceEventKind {
+    /// Already in the channel when the recording started.
+    Present,
+    /// Joined the channel.
+    Join,
+    /// Left the channel.
+    Leave,
+    /// Moved in from another channel.
+    MoveIn,
+    /// Moved out to another channel.
+    MoveOut,
+    /// Muted themselves.
+    Mute,
+    /// Unmuted themselves.
+    Unmute,
+    /// Deafened themselves.
+    Deafen,
+    /// Undeafened themselves.
+    Undeafen,
+    /// Muted by a moderator.
+    ServerMute,
+    /// Unmuted by a moderator.
+    ServerUnmute,
+    /// Deafened by a moderator.
+    ServerDeafen,
+    /// Undeafened by a moderator.
+    ServerUndeafen,
+    /// Started streaming.
+    StreamStart,
+    /// Stopped streaming.
+    StreamStop,
+    /// Turned their camera on.
+    VideoStart,
+    /// Turned their camera off.
+    VideoStop,
+}
+
+/// A change in someone's voice state in the recorded channel.
+#[derive(Debug, PartialEq)]
+pub struct VoiceEvent {
+    /// The user.
+    pub user: UserId,
+    /// The user's name, if known.
+    pub name: Option<String>,
+    /// What changed.
+    pub kind: VoiceEventKind,
+    /// When it happened.
+    pub at: Instant,
+}
+
+/// A voice state flag, and the events for it being turned on and off.
+type Flag = (fn(&VoiceState) -> bool, VoiceEventKind, VoiceEventKind);
+
+/// Works out what happened in `channel` between two voice states of the same user.
+/// A user who has just arrived is reported along with whichever of their flags are already set.
+pub fn voice_state_events(old: Option<&VoiceState>, new: &VoiceState, channel: ChannelId) -> Vec<VoiceEventKind> {
+    let was_in = old.is_some_and(|x| x.channel_id == Some(channel));
+    let is_in = new.channel_id == Some(channel);
+
+    let mut events = Vec::new();
+
+    if was_in && !is_in {
+        events.push(if new.channel_id.is_some() { VoiceEventKind::MoveOut } else { VoiceEventKind::Leave });
+        return events;
+    }
+    if !is_in {
+        return events;
+    }
+
+    if !was_in {
+        let moved = old.and_then(|x| x.channel_id).is_some_and(|x| x != channel);
+        events.push(if moved { VoiceEventKind::MoveIn } else { VoiceEventKind::Join });
+    }
+
+    // Compare against a fresh state with nothing set if the user has only just arrived.
+    let old = old.filter(|_| was_in);
+
+    let flags: [Flag; 6] = [
+        (|x: &VoiceState| x.self_mute, VoiceEventKind::Mute, VoiceEventKind::Unmute),
+        (|x: &VoiceState| x.self_deaf, VoiceEventKind::Deafen, VoiceEventKind::Undeafen),
+        (|x: &VoiceState| x.mute, VoiceEventKind::ServerMute, VoiceEventKind::ServerUnmute),
+        (|x: &VoiceState| x.deaf, VoiceEventKind::ServerDeafen, VoiceEventKind::ServerUndeafen),
+        (|x: &VoiceState| x.self_stream.unwrap_or(false), VoiceEventKind::StreamStart, VoiceEventKind::StreamStop),
+        (|x: &VoiceState| x.self_video, VoiceEventKind::VideoStart, VoiceEventKind::VideoStop),
+    ];
+
+    for (flag, on, off) in flags {
+        let before = old.is_some_and(flag);
+        let after = flag(new);
+        if after && !before {
+            events.push(on);
+        } else if before && !after {
+            events.push(off);
+        }
+    }
+
+    events
+}
+
+#[derive(Debug, Serialize)]
+struct TimelineEntry<'a> {
+    user_id: String,
+    name: Option<&'a str>,
+    event: VoiceEventKind,
+    offset_ms: u64,
+    offset_samples: u64,
+    offset_ticks: u64,
+}
+
+/// Writes the voice events of a session as `timeline.json` and a human-readable `timeline.txt`.
+pub async fn write_timeline(dir: &Path, epoch: Instant, events: &[VoiceEvent]) -> Result<(), RecorderError> {
+    let mut entries = Vec::with_capacity(events.len());
+    let mut text = String::new();
+
+    for event in events {
+        let offset = event.at.saturating_duration_since(epoch);
+        let offset_samples = elapsed_samples_at(epoch, event.at);
+
+        let seconds = offset.as_secs();
+        text += &format!("[+{:02}:{:02}:{:02}.{:03}] ", seconds / 3600, (seconds / 60) % 60, seconds % 60, offset.subsec_millis());
+        match &event.name {
+            Some(name) => text += &format!("{name} ({})", event.user),
+            None => text += &event.user.to_string(),
+        }
+        text += &format!(": {:?}\n", event.kind);
+
+        entries.push(TimelineEntry {
+            user_id: event.user.to_string(),
+            name: event.name.as_deref(),
+            event: event.kind,
+            offset_ms: offset.as_millis() as u64,
+            offset_samples,
+            offset_ticks: offset_samples / TICK_SAMPLES,
+        });
+    }
+
+    let json_path = dir.join(TIMELINE_JSON_FILE);
+    let json = serde_json::to_vec_pretty(&entries).map_err(|e| RecorderError::io("serialize", &json_path, e.into()))?;
+    tokio::fs::write(&json_path, json).await.map_err(|e| RecorderError::io("write to", &json_path, e))?;
+
+    let text_path = dir.join(TIMELINE_TEXT_FILE);
+    tokio::fs::write(&text_path, text).await.map_err(|e| RecorderError::io("write to", &text_path, e))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use VoiceEventKind::*;
+
+    const CHANNEL: ChannelId = ChannelId::new(10);
+    const OTHER: ChannelId = ChannelId::new(20);
+
+    /// A voice state with nothing set, in `channel` if any.
+    fn state(channel: Option<ChannelId>) -> VoiceState {
+        serde_json::from_value(serde_json::json!({
+            "channel_id": channel,
+            "deaf": false,
+            "mute": false,
+            "self_deaf": false,
+            "self_mute": false,
+            "self_video": false,
+            "session_id": "session",
+            "suppress": false,
+            "user_id": "1",
+        })).unwrap()
+    }
+
+    #[test]
+    fn join_and_leave() {
+        assert_eq!(voice_state_events(None, &state(Some(CHANNEL)), CHANNEL), [Join]);
+        assert_eq!(voice_state_events(Some(&state(None)), &state(Some(CHANNEL)), CHANNEL), [Join]);
+        assert_eq!(voice_state_events(Some(&state(Some(CHANNEL))), &state(None), CHANNEL), [Leave]);
+    }
+
+    #[test]
+    fn move_in_and_out() {
+        assert_eq!(voice_state_events(Some(&state(Some(OTHER))), &state(Some(CHANNEL)), CHANNEL), [MoveIn]);
+        assert_eq!(voice_state_events(Some(&state(Some(CHANNEL))), &state(Some(OTHER)), CHANNEL), [MoveOut]);
+    }
+
+    #[test]
+    fn ignores_other_channels() {
+        assert!(voice_state_events(None, &state(Some(OTHER)), CHANNEL).is_empty());
+
+        let mut muted = state(Some(OTHER));
+        muted.self_mute = true;
+        assert!(voice_state_events(Some(&state(Some(OTHER))), &muted, CHANNEL).is_empty());
+    }
+
+    #[test]
+    fn arriving_reports_flags_already_set() {
+        let mut new = state(Some(CHANNEL));
+        new.self_mute = true;
+        new.deaf = true;
+
+        // Flags set while in another channel are reported on arrival, not compared against.
+        let mut old = state(Some(OTHER));
+        old.self_mute = true;
+
+        assert_eq!(voice_state_events(None, &new, CHANNEL), [Join, Mute, ServerDeafen]);
+        assert_eq!(voice_state_events(Some(&old), &new, CHANNEL), [MoveIn, Mute, ServerDeafen]);
+    }
+
+    #[test]
+    fn flags_turning_on_and_off() {
+        let off = state(Some(CHANNEL));
+        let mut on = state(Some(CHANNEL));
+        on.self_stream = Some(true);
+        on.self_video = true;
+
+        assert_eq!(voice_state_events(Some(&off), &on, CHANNEL), [StreamStart, VideoStart]);
+        assert_eq!(voice_state_events(Some(&on), &off, CHANNEL), [StreamStop, VideoStop]);
+        assert!(voice_state_events(Some(&on), &on, CHANNEL).is_empty());
+
+        // Leaving reports only the leave, not every flag turning off.
+        assert_eq!(voice_state_events(Some(&on), &state(None), CHANNEL), [Leave]);
+    }
+}
\ No newline at end of file
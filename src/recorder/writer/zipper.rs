@@ -1,34 +1,50 @@
 use std::path::PathBuf;
 use async_zip::base::write::ZipFileWriter;
 use async_zip::{Compression, ZipEntryBuilder};
+use futures_lite::AsyncWriteExt;
 use serenity::all::GuildId;
 use tokio::fs::{read_dir, File};
 use tokio::io::AsyncReadExt;
-use tokio::sync::oneshot::Sender;
+use tokio::sync::watch;
+use crate::recorder::ZipProgress;
+use crate::recorder::error::RecorderError;
+
+/// Size of the buffer each file is streamed into the archive through.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Opus is already compressed, so deflating it only costs time.
+fn compression_for(file_name: &str) -> Compression {
+    if file_name.ends_with(".opus") {
+        Compression::Stored
+    } else {
+        Compression::Deflate
+    }
+}
 
-async fn do_zip_files(directory: PathBuf, zip_name: String, guild_id: GuildId) -> Result<PathBuf, String> {
+pub async fn zip_files(directory: PathBuf, zip_name: String, guild_id: GuildId, progress: watch::Sender<ZipProgress>) -> Result<PathBuf, RecorderError> {
     let zip_path = directory.join(zip_name);
     debug!("[{guild_id}] Creating zip archive at {}", zip_path.display());
 
-    let mut zip_file = match File::create(&zip_path).await {
-        Ok(x) => x,
-        Err(e) => {
-            error!("[{guild_id}] Failed to create zip file {}: {e:?}", zip_path.display());
-            return Err(format!("Failed to create zip file: {e}"));
-        }
-    };
-
-    let mut zip_writer = ZipFileWriter::with_tokio(&mut zip_file);
-
     let mut dir_entries = match read_dir(&directory).await {
         Ok(x) => x,
         Err(e) => {
             error!("[{guild_id}] Failed to read recording directory {}: {e:?}", directory.display());
-            return Err(format!("Failed to create zip archive: {e}"));
+            return Err(RecorderError::io("read", &directory, e));
         }
     };
 
-    while let Ok(Some(entry)) = dir_entries.next_entry().await {
+    // Only names and sizes are collected here; each file is opened when it is written, so that long sessions with
+    // many segments don't run out of file descriptors.
+    let mut files = Vec::new();
+    loop {
+        let entry = match dir_entries.next_entry().await {
+            Ok(Some(x)) => x,
+            Ok(None) => break,
+            Err(e) => {
+                error!("[{guild_id}] Failed to read recording directory {}: {e:?}", directory.display());
+                return Err(RecorderError::io("read", &directory, e));
+            }
+        };
         let path = entry.path();
 
         if path == zip_path {
@@ -36,46 +52,90 @@ async fn do_zip_files(directory: PathBuf, zip_name: String, guild_id: GuildId) -
         }
 
         let file_name = match path.file_name() {
-            Some(x) => x,
+            Some(x) => x.to_string_lossy().to_string(),
             None => {
                 error!("[{guild_id}] Failed to get file name! (this should never happen)");
                 continue
             }
         };
 
-        let file_name = file_name.to_string_lossy().to_string();
+        let metadata = match entry.metadata().await {
+            Ok(x) => x,
+            Err(e) => {
+                error!("[{guild_id}] Failed to read metadata of {file_name}: {e:?}");
+                return Err(RecorderError::io("read metadata of", &path, e));
+            }
+        };
 
+        // Recordings are flat, so anything nested (e.g. a clips directory) is left out of the archive.
+        if !metadata.is_file() {
+            warn!("[{guild_id}] Skipping {file_name} in zip archive: not a regular file");
+            continue
+        }
+
+        files.push((file_name, metadata.len()));
+    }
+
+    progress.send_replace(ZipProgress {
+        files_done: 0,
+        files_total: files.len(),
+        bytes_done: 0,
+        bytes_total: files.iter().map(|x| x.1).sum(),
+    });
+
+    let mut zip_file = match File::create(&zip_path).await {
+        Ok(x) => x,
+        Err(e) => {
+            error!("[{guild_id}] Failed to create zip file {}: {e:?}", zip_path.display());
+            return Err(RecorderError::io("create", &zip_path, e));
+        }
+    };
+
+    let mut zip_writer = ZipFileWriter::with_tokio(&mut zip_file);
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+
+    for (file_name, _) in files {
         debug!("[{guild_id}] Adding {file_name} to zip archive...");
 
-        let mut file = match File::open(&path).await {
+        // A file that can't be read fails the archive rather than leaving out a track the checksums still list.
+        let file_path = directory.join(&file_name);
+        let mut file = match File::open(&file_path).await {
             Ok(x) => x,
             Err(e) => {
-                error!("[{guild_id}] Failed to open file for reading: {e:?}");
-                continue
+                error!("[{guild_id}] Failed to open {file_name} for reading: {e:?}");
+                return Err(RecorderError::io("open", &file_path, e));
+            }
+        };
+
+        let builder = ZipEntryBuilder::new(file_name.as_str().into(), compression_for(&file_name));
+        let mut entry_writer = match zip_writer.write_entry_stream(builder).await {
+            Ok(x) => x,
+            Err(e) => {
+                error!("[{guild_id}] Failed to start zip entry: {e:?}");
+                return Err(RecorderError::Zip { path: zip_path, source: e });
             }
         };
 
-        let file_size = file.metadata().await.unwrap().len() as usize;
+        // An entry that has been started can't be abandoned, so any error from here on ruins the whole archive.
+        loop {
+            let read = file.read(&mut buffer).await.map_err(|e| RecorderError::io("read", &file_path, e))?;
+            if read == 0 {
+                break;
+            }
 
-        trace!("[{guild_id}] Reading file {file_name} into buffer...");
-        let mut buffer = Vec::with_capacity(file_size);
-        if let Err(e) = file.read_to_end(&mut buffer).await {
-            error!("[{guild_id}] Failed to read file: {e:?}");
-            continue
+            entry_writer.write_all(&buffer[..read]).await.map_err(|e| RecorderError::io("write to", &zip_path, e))?;
+            progress.send_modify(|x| x.bytes_done += read as u64);
         }
 
-        trace!("[{guild_id}] Writing buffer into zip...");
-        let builder = ZipEntryBuilder::new(file_name.into(), Compression::Deflate);
-        if let Err(e) = zip_writer.write_entry_whole(builder, &buffer).await {
-            error!("[{guild_id}] Failed to write to zip entry: {e:?}");
-            continue
-        }
+        trace!("[{guild_id}] Closing zip entry {file_name}...");
+        entry_writer.close().await.map_err(|source| RecorderError::Zip { path: zip_path.clone(), source })?;
+        progress.send_modify(|x| x.files_done += 1);
     }
 
     trace!("[{guild_id}] Finalizing zip...");
     if let Err(e) = zip_writer.close().await {
         error!("[{guild_id}] Failed to finalize zip file: {e:?}");
-        return Err(format!("Failed to finalize zip file: {e}"));
+        return Err(RecorderError::Zip { path: zip_path, source: e });
     }
 
     info!("[{guild_id}] Wrote zip: {}", zip_path.display());
@@ -83,9 +143,28 @@ async fn do_zip_files(directory: PathBuf, zip_name: String, guild_id: GuildId) -
     Ok(zip_path)
 }
 
-pub async fn zip_files(directory: PathBuf, zip_name: String, guild_id: GuildId, zip_tx: Sender<Result<PathBuf, String>>) {
-    let res = do_zip_files(directory, zip_name, guild_id).await;
-    if let Err(_) = zip_tx.send(res) {
-        error!("[{guild_id}] Failed to send zip files result to the channel!");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn skips_directories_and_counts_only_archived_bytes() {
+        let dir = std::env::temp_dir().join(format!("disrecord_zip_test_{}", std::process::id()));
+        tokio::fs::create_dir_all(dir.join("clips")).await.unwrap();
+        tokio::fs::write(dir.join("alice.opus"), vec![1u8; 3000]).await.unwrap();
+        tokio::fs::write(dir.join("metadata.json"), b"{}").await.unwrap();
+        tokio::fs::write(dir.join("clips").join("clip.opus"), vec![2u8; 500]).await.unwrap();
+
+        let (tx, rx) = watch::channel(ZipProgress::default());
+        let zip_path = zip_files(dir.clone(), "session.zip".to_string(), GuildId::new(1), tx).await.unwrap();
+        assert!(zip_path.is_file());
+
+        let progress = *rx.borrow();
+        assert_eq!(progress.files_total, 2);
+        assert_eq!(progress.files_done, 2);
+        assert_eq!(progress.bytes_total, 3002);
+        assert_eq!(progress.bytes_done, progress.bytes_total);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
     }
 }
\ No newline at end of file
@@ -0,0 +1,135 @@
+use crate::commands::reset_presence;
+use disrecord::recorder::recorder::Recorder;
+use disrecord::recorder::storage::StorageLocation;
+use serenity::all::{Context, CreateAttachment, CreateMessage, GuildId};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinSet;
+
+const NOTICE: &str = "Recording stopped due to bot restart.";
+
+/// Finalizes every running recording when the bot is asked to stop.
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    shutting_down: AtomicBool,
+    /// A context from the gateway, needed to leave calls and post notices from outside an event.
+    ctx: Mutex<Option<Context>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn get(ctx: &Context) -> Option<Arc<Self>> {
+        let data = ctx.data.read().await;
+        data.get::<Self>().cloned()
+    }
+
+    pub fn set_context(&self, ctx: &Context) {
+        *self.ctx.lock().unwrap() = Some(ctx.clone());
+    }
+
+    /// Whether new commands should be turned away.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /// Stops accepting commands, finishes every recording and waits for them to be packaged.
+    pub async fn run(&self, recorder: Arc<Recorder>) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+
+        let guilds = recorder.active_guilds();
+        if guilds.is_empty() {
+            return;
+        }
+
+        let ctx = match self.ctx.lock().unwrap().clone() {
+            Some(x) => x,
+            None => {
+                error!("Never connected to the gateway, can't finish {} recording(s)!", guilds.len());
+                return;
+            }
+        };
+
+        info!("Finishing {} recording(s) before shutting down", guilds.len());
+
+        let mut tasks = JoinSet::new();
+        for guild_id in guilds {
+            tasks.spawn(finish_recording(ctx.clone(), recorder.clone(), guild_id));
+        }
+
+        while let Some(res) = tasks.join_next().await {
+            if let Err(e) = res {
+                error!("Shutdown task failed: {e:?}");
+            }
+        }
+    }
+}
+
+async fn finish_recording(ctx: Context, recorder: Arc<Recorder>, guild_id: GuildId) {
+    let res = recorder.finish(&ctx, guild_id).await;
+    reset_presence(&ctx, guild_id).await;
+
+    let summary = match res {
+        Ok(x) => x,
+        Err(e) => {
+            error!("[{guild_id}] Failed to finish recording on shutdown: {e}");
+            return;
+        }
+    };
+
+    let message = match summary.zip_rx.await {
+        Ok(Ok(stored)) => match stored.location {
+            StorageLocation::Local(zip_path) => match CreateAttachment::path(&zip_path).await {
+                Ok(attachment) => CreateMessage::new().content(format!("{NOTICE} Recording ID: `{}`", summary.id)).add_file(attachment),
+                Err(e) => {
+                    error!("[{guild_id}] Failed to create attachment: {e:?}");
+                    CreateMessage::new().content(format!("{NOTICE} Recording saved to `{}`", zip_path.display()))
+                }
+            },
+            location => CreateMessage::new().content(format!("{NOTICE} Recording saved to `{location}`")),
+        },
+        Ok(Err(e)) => {
+            error!("[{guild_id}] Failed to package recording on shutdown: {e}");
+            CreateMessage::new().content(format!("{NOTICE} Failed to package recording `{}`: {e}", summary.id))
+        }
+        Err(e) => {
+            error!("[{guild_id}] Failed to receive zipper message: {e:?}");
+            CreateMessage::new().content(format!("{NOTICE} Failed to package recording `{}`!", summary.id))
+        }
+    };
+
+    if let Err(e) = summary.channel_id.send_message(&ctx, message).await {
+        error!("[{guild_id}] Failed to post shutdown notice (file too large?): {e:?}");
+        let fallback = CreateMessage::new().content(format!("{NOTICE} Recording ID: `{}`", summary.id));
+        if let Err(e) = summary.channel_id.send_message(&ctx, fallback).await {
+            error!("[{guild_id}] Failed to post fallback shutdown notice: {e:?}");
+        }
+    }
+
+    info!("[{guild_id}] Finished recording on shutdown");
+}
+
+/// Resolves when the process receives SIGINT or SIGTERM.
+#[cfg(unix)]
+pub async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT, shutting down..."),
+        _ = sigterm.recv() => info!("Received SIGTERM, shutting down..."),
+    }
+}
+
+/// Resolves when the process receives Ctrl+C.
+#[cfg(not(unix))]
+pub async fn wait_for_signal() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!("Failed to listen for Ctrl+C: {e:?}");
+        std::future::pending::<()>().await;
+    }
+    info!("Received Ctrl+C, shutting down...");
+}
\ No newline at end of file
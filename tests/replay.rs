@@ -0,0 +1,161 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use disrecord::recorder::durability::SyncPolicy;
+use disrecord::recorder::recorder::Recorder;
+use disrecord::recorder::storage::local::LocalStorage;
+use disrecord::recorder::track_name::{TrackNameTemplate, DEFAULT_TRACK_NAME};
+use disrecord::recorder::{RecorderConfig, SegmentPolicy};
+
+/// Two speakers, one of whom loses a packet and one whose RTP sequence wraps around.
+const FIXTURE: &str = "tests/fixtures/session.dump";
+/// The tracks the fixture replays to. Set `DISRECORD_BLESS=1` to rewrite them after an intended change to the output.
+const GOLDEN_DIR: &str = "tests/fixtures/golden";
+
+fn fixture_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+fn config(base_dir: &Path) -> RecorderConfig {
+    RecorderConfig {
+        base_dir: base_dir.to_path_buf(),
+        subdir_fmt: "%Y_%m_%d_%H_%M_%S".to_string(),
+        track_name: TrackNameTemplate::parse(DEFAULT_TRACK_NAME).unwrap(),
+        jitter_buffer_depth: 3,
+        queue_depth: 1024,
+        repair_lost_packets: false,
+        sync_policy: SyncPolicy::Never,
+        segment_policy: SegmentPolicy::default(),
+        clip_history: Duration::ZERO,
+        storage: Arc::new(LocalStorage::new(base_dir.to_path_buf())),
+        encryption: None,
+        capture_packets: false,
+        capture_chat: false,
+    }
+}
+
+/// Replays the fixture into a directory of its own, returning the name and contents of every track.
+async fn replay(name: &str) -> Vec<(String, Vec<u8>)> {
+    replay_dump(name, &fixture_path(FIXTURE)).await
+}
+
+/// Replays `dump` into a directory of its own, returning the name and contents of every track.
+async fn replay_dump(name: &str, dump: &Path) -> Vec<(String, Vec<u8>)> {
+    let base_dir = std::env::temp_dir().join(format!("disrecord_replay_{name}_{}", std::process::id()));
+    _ = tokio::fs::remove_dir_all(&base_dir).await;
+
+    let recorder = Recorder::new(config(&base_dir));
+    let summary = recorder.replay(dump).await.unwrap();
+    assert_eq!(summary.write_errors, 0, "first error: {:?}", summary.first_error);
+    summary.zip_rx.await.unwrap().unwrap();
+
+    let mut tracks = Vec::new();
+    for entry in std::fs::read_dir(base_dir.join("100").join(&summary.id)).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|x| x == "opus") {
+            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+            tracks.push((file_name, std::fs::read(&path).unwrap()));
+        }
+    }
+    tracks.sort();
+
+    tokio::fs::remove_dir_all(&base_dir).await.unwrap();
+    tracks
+}
+
+#[tokio::test]
+async fn replay_is_reproducible() {
+    let first = replay("first").await;
+    let second = replay("second").await;
+
+    assert_eq!(first.iter().map(|x| &x.0).collect::<Vec<_>>(), ["01_Alice_1001.opus", "02_bob_1002.opus"]);
+    for ((name, a), (_, b)) in first.iter().zip(&second) {
+        assert!(a == b, "{name} differs between two replays of the same dump");
+    }
+
+    let golden_dir = fixture_path(GOLDEN_DIR);
+    if std::env::var_os("DISRECORD_BLESS").is_some() {
+        std::fs::create_dir_all(&golden_dir).unwrap();
+        for (name, data) in &first {
+            std::fs::write(golden_dir.join(name), data).unwrap();
+        }
+    }
+
+    for (name, data) in &first {
+        let golden = std::fs::read(golden_dir.join(name)).unwrap_or_else(|e| panic!("Failed to read golden {name}: {e}"));
+        assert!(*data == golden, "{name} differs from its golden file; rerun with DISRECORD_BLESS=1 if that is intended");
+    }
+}
+
+/// Offset of the first field after the tag and position of each record in a dump, along with its tag.
+fn record_offsets(dump: &[u8]) -> Vec<(u8, usize)> {
+    let read_u32 = |at: usize| u32::from_le_bytes(dump[at..at + 4].try_into().unwrap()) as usize;
+    let skip_string = |at: usize| match read_u32(at) {
+        len if len == u32::MAX as usize => at + 4,
+        len => at + 4 + len,
+    };
+
+    let mut records = Vec::new();
+    let mut at = dump.iter().position(|x| *x == b'\n').unwrap() + 1;
+    at += dump[at..].iter().position(|x| *x == b'\n').unwrap() + 1;
+
+    while at < dump.len() {
+        let tag = dump[at];
+        let body = at + 1 + 8;
+        records.push((tag, body));
+
+        at = match tag {
+            0 => skip_string(skip_string(body + 8 + 4)),
+            _ => {
+                let mut at = body + 4;
+                for _ in 0..read_u32(body) {
+                    at += 8 + 4 + 2;
+                    at += 4 + read_u32(at);
+                }
+                at
+            }
+        };
+    }
+
+    records
+}
+
+#[tokio::test]
+async fn damaged_dumps_end_the_replay_early() {
+    let fixture = std::fs::read(fixture_path(FIXTURE)).unwrap();
+    let full = replay("full").await;
+    let records = record_offsets(&fixture);
+    let dir = std::env::temp_dir().join(format!("disrecord_damaged_dumps_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // Cut off partway through the last record, as a crash would.
+    let truncated = dir.join("truncated.dump");
+    std::fs::write(&truncated, &fixture[..fixture.len() - 5]).unwrap();
+
+    // Lengths that would otherwise be allocated up front.
+    let (_, last_tick) = *records.iter().rev().find(|x| x.0 == 1).unwrap();
+    let mut huge_count = fixture.clone();
+    huge_count[last_tick..last_tick + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    let huge_count_path = dir.join("huge_count.dump");
+    std::fs::write(&huge_count_path, &huge_count).unwrap();
+
+    let payload_len = last_tick + 4 + 8 + 4 + 2;
+    let mut huge_payload = fixture.clone();
+    huge_payload[payload_len..payload_len + 4].copy_from_slice(&(u32::MAX - 1).to_le_bytes());
+    let huge_payload_path = dir.join("huge_payload.dump");
+    std::fs::write(&huge_payload_path, &huge_payload).unwrap();
+
+    let (_, last_user) = *records.iter().rev().find(|x| x.0 == 0).unwrap();
+    let username_len = last_user + 8 + 4;
+    let mut huge_name = fixture.clone();
+    huge_name[username_len..username_len + 4].copy_from_slice(&(u32::MAX - 1).to_le_bytes());
+    let huge_name_path = dir.join("huge_name.dump");
+    std::fs::write(&huge_name_path, &huge_name).unwrap();
+
+    for (name, dump) in [("truncated", &truncated), ("huge_count", &huge_count_path), ("huge_payload", &huge_payload_path), ("huge_name", &huge_name_path)] {
+        let tracks = replay_dump(name, dump).await;
+        assert!(tracks.len() <= full.len(), "{name} replayed to more tracks than the intact dump");
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
\ No newline at end of file